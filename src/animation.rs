@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::path::Path;
+use opengl_graphics::Texture;
+
+use assets::*;
+use types::*;
+
+
+pub type SpriteIndex = u16;
+
+#[derive(Clone,Copy)]
+pub struct AnimationFrame {
+  pub sprite_index: SpriteIndex,
+  pub duration: Seconds,
+}
+
+pub struct Animation {
+  pub frames: Vec<AnimationFrame>,
+  pub looping: bool, // otherwise the last frame stays on screen once the animation is over
+}
+
+// A grid of sprites, each the size of a cell, grouped into named animations.
+pub struct SpriteSheet {
+  pub texture: Texture,
+  pub grid_width: SpriteIndex,
+  pub animations: HashMap<String, Animation>,
+}
+
+struct SpriteSheetDescription {
+  grid_width: Option<SpriteIndex>,
+  animations: HashMap<String, Animation>,
+}
+
+
+fn parse_frame(word: &str) -> Result<AnimationFrame, String> {
+  let mut parts = word.split(':');
+  let sprite_index = parts.next().and_then(|part| part.parse().ok());
+  let duration = parts.next().and_then(|part| part.parse().ok());
+  
+  match (sprite_index, duration, parts.next()) {
+    (Some(sprite_index), Some(duration), None) => Ok(AnimationFrame { sprite_index: sprite_index, duration: duration }),
+    _ => Err(format!("syntax error in sprite sheet description: expected SPRITE:SECONDS, not {:?}", word)),
+  }
+}
+
+// A sprite sheet description looks like this:
+//
+//   # comment
+//   grid_width 4
+//
+//   # each frame is a sprite index followed by the number of seconds it stays on screen
+//   idle            0:2.0 1:0.15
+//   death      once 12:0.3 13:0.15 14:0.15 15:0.3 16:0.1
+fn parse_sprite_sheet_description(text: &str) -> Result<SpriteSheetDescription, String> {
+  let mut grid_width = None;
+  let mut animations = HashMap::new();
+  
+  for line in text.lines() {
+    let line = line.split('#').next().unwrap();
+    let mut words = line.split_whitespace().peekable();
+    
+    match words.next() {
+      None               => {},
+      Some("grid_width") => {
+        grid_width = words.next().and_then(|word| word.parse().ok());
+      },
+      Some(name)         => {
+        let looping = words.peek() != Some(&"once");
+        if !looping {
+          words.next();
+        }
+        
+        let frames: Vec<AnimationFrame> = words.map(parse_frame).collect::<Result<_, _>>()?;
+        if frames.is_empty() {
+          return Err(format!("syntax error in sprite sheet description: animation {:?} has no frames", name));
+        }
+        
+        animations.insert(name.to_string(), Animation { frames: frames, looping: looping });
+      },
+    }
+  }
+  
+  Ok(SpriteSheetDescription {
+    grid_width: grid_width,
+    animations: animations,
+  })
+}
+
+// Unlike fonts, the descriptor isn't always next to the image, because a theme may replace the images of a sprite
+// sheet without changing its animations.
+pub fn load_sprite_sheet(path: &Path, descriptor_path: &Path) -> Result<SpriteSheet, String> {
+  let description = parse_sprite_sheet_description(&read_text_asset(descriptor_path)?)
+                      .map_err(|err| format!("{}: {}", descriptor_path.display(), err))?;
+  
+  Ok(SpriteSheet {
+    texture: load_texture_asset(path)?,
+    grid_width: description.grid_width.ok_or(format!("{}: sprite sheet description has no grid_width",
+                                                     descriptor_path.display()))?,
+    animations: description.animations,
+  })
+}
+
+
+// The sprite to display `dt` seconds after the start of the animation. Missing animations fall back to "idle", so
+// a sprite sheet with a single sprite is still valid.
+pub fn animation_sprite_index(sprite_sheet: &SpriteSheet, name: &str, dt: Seconds) -> SpriteIndex {
+  let animation = match sprite_sheet.animations.get(name).or(sprite_sheet.animations.get("idle")) {
+    Some(animation) => animation,
+    None            => return 0,
+  };
+  
+  let total_duration: Seconds = animation.frames.iter().map(|frame| frame.duration).sum();
+  let mut remaining = if animation.looping && total_duration > 0.0 {
+                        dt.max(0.0) % total_duration
+                      } else {
+                        dt.max(0.0)
+                      };
+  for frame in &animation.frames {
+    if remaining < frame.duration {
+      return frame.sprite_index;
+    }
+    remaining -= frame.duration;
+  }
+  
+  animation.frames.last().unwrap().sprite_index
+}
@@ -0,0 +1,122 @@
+extern crate image;
+extern crate opengl_graphics;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{ Path, PathBuf };
+use opengl_graphics::{ Texture, TextureSettings };
+
+use settings::*;
+
+
+// The default images are compiled into the executable, so the game can still start if it can't find its "images"
+// directory. The other assets are optional: the game falls back to the English texts, and plays without scripts,
+// themes or sounds.
+const EMBEDDED_ASSETS: &'static [(&'static str, &'static [u8])] = &[
+  ("images/big-font.png",   include_bytes!("../images/big-font.png")),
+  ("images/big-font.txt",   include_bytes!("../images/big-font.txt")),
+  ("images/conveyor.png",   include_bytes!("../images/conveyor.png")),
+  ("images/floor.png",      include_bytes!("../images/floor.png")),
+  ("images/goal-top.png",   include_bytes!("../images/goal-top.png")),
+  ("images/goal.png",       include_bytes!("../images/goal.png")),
+  ("images/ice.png",        include_bytes!("../images/ice.png")),
+  ("images/player.png",     include_bytes!("../images/player.png")),
+  ("images/player.txt",     include_bytes!("../images/player.txt")),
+  ("images/sign.png",       include_bytes!("../images/sign.png")),
+  ("images/sink.png",       include_bytes!("../images/sink.png")),
+  ("images/small-font.png", include_bytes!("../images/small-font.png")),
+  ("images/small-font.txt", include_bytes!("../images/small-font.txt")),
+  ("images/spawner.png",    include_bytes!("../images/spawner.png")),
+  ("images/spiny.png",      include_bytes!("../images/spiny.png")),
+  ("images/spiny.txt",      include_bytes!("../images/spiny.txt")),
+  ("images/start-top.png",  include_bytes!("../images/start-top.png")),
+  ("images/start.png",      include_bytes!("../images/start.png")),
+  ("images/wall.png",       include_bytes!("../images/wall.png")),
+  ("images/white-font.png", include_bytes!("../images/white-font.png")),
+  ("images/white-font.txt", include_bytes!("../images/white-font.txt")),
+];
+
+
+// The asset paths, e.g. "images/spiny.png", are relative to the first of those directories which contains them:
+//
+//   1. the directory given with "--assets DIR"
+//   2. the directory containing the executable, or one of its two parents, so "target/release/ludum-dare-37" finds
+//      the assets of the repository
+//   3. the current directory
+pub fn asset_roots() -> Vec<PathBuf> {
+  let mut roots = Vec::new();
+  
+  if let Some(directory) = command_line_option("assets") {
+    roots.push(PathBuf::from(directory));
+  }
+  if let Some(directory) = env::current_exe().ok().and_then(|exe| exe.parent().map(|parent| parent.to_path_buf())) {
+    roots.extend(directory.ancestors().take(3).map(|ancestor| ancestor.to_path_buf()));
+  }
+  if let Ok(directory) = env::current_dir() {
+    roots.push(directory);
+  }
+  
+  let mut unique_roots = Vec::new();
+  for root in roots {
+    if !unique_roots.contains(&root) {
+      unique_roots.push(root);
+    }
+  }
+  
+  unique_roots
+}
+
+// Also works for directories.
+pub fn find_asset(relative_path: &Path) -> Result<PathBuf, String> {
+  let candidates: Vec<PathBuf> = asset_roots().iter().map(|root| root.join(relative_path)).collect();
+  if let Some(path) = candidates.iter().find(|path| path.exists()) {
+    return Ok(path.clone());
+  }
+  
+  let tried: Vec<String> = candidates.iter().map(|path| path.display().to_string()).collect();
+  Err(format!("could not find {}, tried {}", relative_path.display(), tried.join(", ")))
+}
+
+pub fn asset_exists(relative_path: &Path) -> bool {
+  find_asset(relative_path).is_ok()
+}
+
+fn embedded_asset(relative_path: &Path) -> Option<&'static [u8]> {
+  EMBEDDED_ASSETS.iter()
+                 .find(|&&(name, _)| Path::new(name) == relative_path)
+                 .map(|&(_, bytes)| bytes)
+}
+
+pub fn read_asset(relative_path: &Path) -> Result<Vec<u8>, String> {
+  match find_asset(relative_path) {
+    Ok(path) => {
+      let mut bytes = Vec::new();
+      File::open(&path).and_then(|mut file| file.read_to_end(&mut bytes))
+                       .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+      
+      Ok(bytes)
+    },
+    Err(err) => embedded_asset(relative_path).map(|bytes| bytes.to_vec()).ok_or(err),
+  }
+}
+
+pub fn read_text_asset(relative_path: &Path) -> Result<String, String> {
+  String::from_utf8(read_asset(relative_path)?)
+        .map_err(|err| format!("could not read {}: {}", relative_path.display(), err))
+}
+
+pub fn load_texture_asset(relative_path: &Path) -> Result<Texture, String> {
+  match find_asset(relative_path) {
+    Ok(path) => {
+      Texture::from_path(&path).map_err(|err| format!("could not load {}: {}", path.display(), err))
+    },
+    Err(err) => {
+      let bytes = embedded_asset(relative_path).ok_or(err)?;
+      let image = image::load_from_memory(bytes)
+                    .map_err(|err| format!("could not load the embedded {}: {}", relative_path.display(), err))?;
+      
+      Ok(Texture::from_image(&image.to_rgba(), &TextureSettings::new()))
+    },
+  }
+}
@@ -0,0 +1,65 @@
+use i18n::*;
+use types::*;
+
+
+// Options which make the game easier, for players who find the spinies too fast or the collisions too strict, e.g.
+// because of a motor impairment. They are chosen in the assist menu, opened with O.
+#[derive(Clone,Copy,PartialEq,Debug,Serialize,Deserialize)]
+pub struct Assists {
+  pub spiny_speed_factor: f64, // multiplies SPINY_SPEED
+  pub hitbox_margin: f64, // larger margins make the collision rectangles smaller
+  pub invulnerable: bool,
+}
+
+pub const DEFAULT_ASSISTS: Assists = Assists {
+  spiny_speed_factor: 1.0,
+  hitbox_margin: DEFAULT_HITBOX_MARGIN,
+  invulnerable: false,
+};
+
+const SPINY_SPEED_FACTORS: &'static [f64] = &[1.0, 0.75, 0.5];
+const HITBOX_MARGINS: &'static [f64] = &[DEFAULT_HITBOX_MARGIN, 0.25, 0.35];
+
+// the last choice closes the menu
+pub const ASSIST_CHOICE_COUNT: u8 = 4;
+pub const ASSIST_DONE_CHOICE: u8 = ASSIST_CHOICE_COUNT - 1;
+
+
+pub fn assisted_spiny_speed(assists: &Assists) -> f64 {
+  SPINY_SPEED * assists.spiny_speed_factor
+}
+
+// The value after `current` in `values`, or the first one if `current` is the last one or isn't one of them.
+fn next_value(values: &[f64], current: f64) -> f64 {
+  match values.iter().position(|&value| value == current) {
+    Some(index) => values[(index + 1) % values.len()],
+    None        => values[0],
+  }
+}
+
+pub fn change_assist(assists: &mut Assists, choice: u8) {
+  match choice {
+    0 => assists.spiny_speed_factor = next_value(SPINY_SPEED_FACTORS, assists.spiny_speed_factor),
+    1 => assists.hitbox_margin      = next_value(HITBOX_MARGINS,      assists.hitbox_margin),
+    2 => assists.invulnerable       = !assists.invulnerable,
+    _ => {},
+  }
+}
+
+// e.g. "Spiny speed: 75%"
+pub fn assist_choices(catalog: &Catalog, assists: &Assists) -> Vec<String> {
+  let text = |key: &str| catalog_text(catalog, key);
+  let hitbox_key = match HITBOX_MARGINS.iter().position(|&margin| margin == assists.hitbox_margin) {
+                     Some(0) => "assist-hitbox-normal",
+                     Some(1) => "assist-hitbox-smaller",
+                     _       => "assist-hitbox-smallest",
+                   };
+  let invulnerable_key = if assists.invulnerable { "assist-on" } else { "assist-off" };
+  
+  vec![
+    format!("{}: {}%", text("assist-spiny-speed"), (assists.spiny_speed_factor * 100.0).round()),
+    format!("{}: {}", text("assist-hitbox"), text(hitbox_key)),
+    format!("{}: {}", text("assist-invulnerable"), text(invulnerable_key)),
+    text("assist-done"),
+  ]
+}
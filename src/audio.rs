@@ -0,0 +1,219 @@
+extern crate rodio;
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+use rodio::{ Decoder, OutputStream, OutputStreamHandle, Sink, Source };
+
+use assets::*;
+use types::*;
+
+
+#[derive(Clone,Copy,PartialEq,Eq,Hash,Debug)]
+pub enum Sound {
+  Footstep,
+  Bump,
+  Bounce,
+  Death,
+  Door,
+  Sign,
+}
+
+#[derive(Clone,Copy,PartialEq,Eq,Hash,Debug)]
+pub enum Music {
+  Calm,
+  Tense,
+}
+
+const SOUNDS: &'static [(Sound, &'static str)] = &[
+  (Sound::Footstep, "sounds/footstep.wav"),
+  (Sound::Bump,     "sounds/bump.wav"),
+  (Sound::Bounce,   "sounds/bounce.wav"),
+  (Sound::Death,    "sounds/death.wav"),
+  (Sound::Door,     "sounds/door.wav"),
+  (Sound::Sign,     "sounds/sign.wav"),
+];
+
+// Each music loop plays from level_min to level_max, inclusive.
+const MUSIC_LOOPS: &'static [(LevelNumber, LevelNumber, Music, &'static str)] = &[
+  (1,  5, Music::Calm,  "sounds/calm.wav"),
+  (6, 11, Music::Tense, "sounds/tense.wav"),
+];
+
+pub fn music_for_level(level_number: LevelNumber) -> Option<Music> {
+  MUSIC_LOOPS.iter()
+             .find(|&&(level_min, level_max, _, _)| level_number >= level_min && level_number <= level_max)
+             .map(|&(_, _, music, _)| music)
+}
+
+
+// The game logic only decides which sounds to play, the implementation decides how, or whether, to play them.
+pub trait Audio {
+  fn play_sound(&mut self, sound: Sound);
+  
+  // Called on every update, so it should do nothing if the music is already playing.
+  fn play_music(&mut self, music: Option<Music>);
+}
+
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+  fn play_sound(&mut self, _sound: Sound) {}
+  fn play_music(&mut self, _music: Option<Music>) {}
+}
+
+// Remembers what would have been played, so we can check it without audio hardware.
+#[cfg(test)]
+pub struct RecordingAudio {
+  pub sounds: Vec<Sound>,
+  pub music: Vec<Option<Music>>, // only the changes
+}
+
+#[cfg(test)]
+impl RecordingAudio {
+  pub fn new() -> RecordingAudio {
+    RecordingAudio {
+      sounds: Vec::new(),
+      music: Vec::new(),
+    }
+  }
+}
+
+#[cfg(test)]
+impl Audio for RecordingAudio {
+  fn play_sound(&mut self, sound: Sound) {
+    self.sounds.push(sound);
+  }
+  
+  fn play_music(&mut self, music: Option<Music>) {
+    if self.music.last() != Some(&music) {
+      self.music.push(music);
+    }
+  }
+}
+
+
+pub struct SpeakerAudio {
+  _stream: OutputStream, // must be kept alive for the sounds to play
+  stream_handle: OutputStreamHandle,
+  
+  // the wav files are small, so we keep them in memory
+  sounds: HashMap<Sound, Vec<u8>>,
+  music_loops: HashMap<Music, Vec<u8>>,
+  
+  music: Option<Music>,
+  music_sink: Option<Sink>,
+}
+
+impl SpeakerAudio {
+  pub fn new() -> Result<SpeakerAudio, String> {
+    let (stream, stream_handle) = OutputStream::try_default().map_err(|err| err.to_string())?;
+    
+    let mut sounds = HashMap::new();
+    for &(sound, path) in SOUNDS {
+      sounds.insert(sound, read_asset(Path::new(path))?);
+    }
+    
+    let mut music_loops = HashMap::new();
+    for &(_, _, music, path) in MUSIC_LOOPS {
+      music_loops.insert(music, read_asset(Path::new(path))?);
+    }
+    
+    Ok(SpeakerAudio {
+      _stream: stream,
+      stream_handle: stream_handle,
+      sounds: sounds,
+      music_loops: music_loops,
+      music: None,
+      music_sink: None,
+    })
+  }
+}
+
+impl Audio for SpeakerAudio {
+  fn play_sound(&mut self, sound: Sound) {
+    if let Some(bytes) = self.sounds.get(&sound) {
+      if let Ok(decoder) = Decoder::new(Cursor::new(bytes.clone())) {
+        // a missing sound effect isn't worth interrupting the game for
+        let _ = self.stream_handle.play_raw(decoder.convert_samples());
+      }
+    }
+  }
+  
+  fn play_music(&mut self, music: Option<Music>) {
+    if music == self.music {
+      return;
+    }
+    self.music = music;
+    
+    if let Some(music_sink) = self.music_sink.take() {
+      music_sink.stop();
+    }
+    
+    if let Some(bytes) = music.and_then(|music| self.music_loops.get(&music)) {
+      if let (Ok(sink), Ok(decoder)) = (Sink::try_new(&self.stream_handle), Decoder::new(Cursor::new(bytes.clone()))) {
+        sink.append(decoder.repeat_infinite());
+        self.music_sink = Some(sink);
+      }
+    }
+  }
+}
+
+// Fall back to silence if there is no audio device or if the sound files are missing.
+pub fn load_audio() -> Box<dyn Audio> {
+  match SpeakerAudio::new() {
+    Ok(speaker_audio) => Box::new(speaker_audio),
+    Err(err)          => {
+      eprintln!("audio disabled: {}", err);
+      Box::new(NullAudio)
+    },
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use scenario::*;
+  
+  
+  #[test]
+  fn dying_plays_the_death_sound() {
+    let scenario = run_scenario("
+      level 4
+      press right x4
+      wait 0.5
+    ");
+    
+    let sounds = &scenario.audio.sounds;
+    assert_eq!(sounds.iter().filter(|&&sound| sound == Sound::Footstep).count(), 4);
+    assert_eq!(sounds.iter().filter(|&&sound| sound == Sound::Death).count(), 1);
+    assert!(!sounds.contains(&Sound::Door));
+  }
+  
+  #[test]
+  fn the_music_changes_with_the_levels() {
+    // invulnerable, to walk through the spinies
+    let scenario = run_scenario("
+      level 5
+      wait 0.5
+      assists
+      press down x2
+      pause
+      assists
+      press right
+      press down
+      press right x6
+      hold down
+      wait 1.5
+      release down
+      hold right
+      wait 3.0
+      release right
+      expect level 6
+    ");
+    
+    assert_eq!(scenario.audio.music, vec![Some(Music::Calm), Some(Music::Tense)]);
+    assert!(scenario.audio.sounds.contains(&Sound::Door));
+  }
+}
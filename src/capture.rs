@@ -0,0 +1,316 @@
+extern crate gif;
+extern crate gl;
+extern crate image;
+extern crate opengl_graphics;
+extern crate piston;
+
+use std::fs::{ self, File };
+use std::path::{ Path, PathBuf };
+use gif::SetParameter;
+use opengl_graphics::GlGraphics;
+use piston::input::RenderArgs;
+use piston::window::Window;
+
+use audio::*;
+use game::*;
+use particle::*;
+use player::*;
+use render::*;
+use replay::*;
+use resources::*;
+use script::*;
+use settings::*;
+use state::*;
+use time_control::*;
+use types::*;
+use types::RawInputEvent::*;
+
+
+// GIF frame delays are in hundredths of a second, so 50 frames per second is as smooth as they get.
+const DEFAULT_CAPTURE_FPS: f64 = 50.0;
+
+// "--capture replay.txt" replays a session recorded with "--record replay.txt" and saves the frames instead of
+// playing the game. "--capture-output" is either a ".gif" file or a directory of numbered PNG frames, and
+// "--capture-from" and "--capture-to" restrict the capture to that time window, in seconds since the start of the
+// session.
+pub struct CaptureSettings {
+  pub replay_path: String,
+  pub output_path: String,
+  pub fps: f64,
+  pub from: Seconds,
+  pub to: Option<Seconds>,
+}
+
+enum CaptureOutput {
+  GifOutput(gif::Encoder<File>, u16), // delay between the frames, in hundredths of a second
+  PngOutput(PathBuf),
+}
+
+fn number_option(name: &str) -> Result<Option<f64>, String> {
+  match command_line_option(name) {
+    Some(value) => value.parse().map(Some).map_err(|_| format!("--{} expects a number, not {:?}", name, value)),
+    None        => Ok(None),
+  }
+}
+
+pub fn capture_setting() -> Result<Option<CaptureSettings>, String> {
+  let replay_path = match command_line_option("capture") {
+    Some(replay_path) => replay_path,
+    None              => return Ok(None),
+  };
+  let fps = number_option("capture-fps")?.unwrap_or(DEFAULT_CAPTURE_FPS);
+  if fps.is_nan() || fps <= 0.0 {
+    return Err(format!("--capture-fps must be positive, not {}", fps));
+  }
+  
+  Ok(Some(CaptureSettings {
+    replay_path: replay_path,
+    output_path: command_line_option("capture-output").unwrap_or("capture.gif".to_string()),
+    fps: fps,
+    from: number_option("capture-from")?.unwrap_or(0.0),
+    to: number_option("capture-to")?,
+  }))
+}
+
+
+fn create_output(settings: &CaptureSettings, width: u32, height: u32) -> Result<CaptureOutput, String> {
+  use self::CaptureOutput::*;
+  
+  let path = Path::new(&settings.output_path);
+  let error = |err: ::std::io::Error| format!("could not create {}: {}", path.display(), err);
+  
+  if path.extension().map_or(false, |extension| extension == "gif") {
+    let file = File::create(path).map_err(&error)?;
+    let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[]).map_err(&error)?;
+    encoder.set(gif::Repeat::Infinite).map_err(&error)?;
+    
+    Ok(GifOutput(encoder, (100.0 / settings.fps).round().max(1.0) as u16))
+  } else {
+    fs::create_dir_all(path).map_err(&error)?;
+    
+    Ok(PngOutput(path.to_path_buf()))
+  }
+}
+
+// The rows of the framebuffer go from bottom to top, the rows of the images go from top to bottom.
+pub fn read_frame(width: u32, height: u32) -> Vec<u8> {
+  let row_length = width as usize * 4;
+  let mut pixels = vec![0; row_length * height as usize];
+  unsafe {
+    gl::ReadPixels(
+      0, 0, width as i32, height as i32,
+      gl::RGBA, gl::UNSIGNED_BYTE,
+      pixels.as_mut_ptr() as *mut gl::types::GLvoid
+    );
+  }
+  
+  let mut flipped = Vec::with_capacity(pixels.len());
+  for row in pixels.chunks(row_length).rev() {
+    flipped.extend_from_slice(row);
+  }
+  
+  flipped
+}
+
+fn write_frame(
+  output: &mut CaptureOutput,
+  frame_number: u32,
+  mut pixels: Vec<u8>,
+  width: u32,
+  height: u32
+) -> Result<(), String> {
+  use self::CaptureOutput::*;
+  
+  match *output {
+    GifOutput(ref mut encoder, delay) => {
+      let mut frame = gif::Frame::from_rgba(width as u16, height as u16, &mut pixels);
+      frame.delay = delay;
+      encoder.write_frame(&frame).map_err(|err| format!("could not write frame {}: {}", frame_number, err))
+    },
+    PngOutput(ref directory)          => {
+      let path = directory.join(format!("frame-{:05}.png", frame_number));
+      image::save_buffer(&path, &pixels, width, height, image::ColorType::RGBA(8))
+           .map_err(|err| format!("could not write {}: {}", path.display(), err))
+    },
+  }
+}
+
+// What the event loop would pass to render, for rendering outside of the event loop.
+pub fn window_render_args<W: Window>(window: &W) -> RenderArgs {
+  let size = window.size();
+  let draw_size = window.draw_size();
+  
+  RenderArgs {
+    ext_dt: 0.0,
+    width: size.width,
+    height: size.height,
+    draw_width: draw_size.width,
+    draw_height: draw_size.height,
+  }
+}
+
+// The state in which the replay starts, with the display settings of this session.
+fn replay_initial_state(replay: &Replay) -> State {
+  let mut state = match replay.initial_state {
+    Some(ref state) => state.clone(),
+    None            => {
+      let mut state = initial_state();
+      if coop_setting() {
+        set_player_count(&mut state, 2);
+      }
+      state
+    },
+  };
+  state.effects = effects_setting();
+  
+  state
+}
+
+// Instead of rendering whenever the event loop asks, we replay the session's events exactly as they were recorded,
+// and render the frames at exactly 0, 1/fps, 2/fps... seconds, so the result is the same regardless of how fast the
+// machine is. Splitting the recorded TimePasses events at the frame times would not be the same session: the player
+// could survive a spiny which killed them. Instead, a frame in the middle of an event shows the state before that
+// event at the frame's time, which is exact since the positions of the player and the spinies are functions of the
+// time. Calls `on_frame` with the frame's number, counted from 0 at the start of the session, and returns the state
+// at the end.
+pub fn replay_frames<F: FnMut(u32, &State) -> Result<(), String>>(
+  replay: &Replay,
+  scripts: &Scripts,
+  fps: f64,
+  to: Seconds,
+  mut on_frame: F
+) -> Result<State, String> {
+  let mut state = replay_initial_state(replay);
+  let mut audio = NullAudio;
+  let mut session_time = 0.0;
+  let mut frame_number = 0;
+  
+  for &event in &replay.events {
+    let mut next_state = state.clone();
+    update(&mut next_state, scripts, &mut audio, event);
+    
+    if let TimePasses(dt) = event {
+      // the game time doesn't pass while a message is displayed, so it can be slower than the session time
+      while frame_number as f64 / fps < session_time + dt && frame_number as f64 / fps <= to {
+        let mut frame_state = state.clone();
+        frame_state.time += (frame_number as f64 / fps - session_time) / dt * (next_state.time - state.time);
+        on_frame(frame_number, &frame_state)?;
+        frame_number += 1;
+      }
+      session_time += dt;
+    }
+    if session_time > to {
+      break;
+    }
+    
+    state = next_state;
+  }
+  
+  while frame_number as f64 / fps <= session_time.min(to) {
+    on_frame(frame_number, &state)?;
+    frame_number += 1;
+  }
+  
+  Ok(state)
+}
+
+// Returns the number of captured frames.
+pub fn run_capture<W: Window>(
+  settings: &CaptureSettings,
+  window: &mut W,
+  resources: &Resources,
+  scripts: &Scripts,
+  gl: &mut GlGraphics
+) -> Result<u32, String> {
+  let replay = load_replay(&settings.replay_path)?;
+  let to = settings.to.unwrap_or(replay_duration(&replay));
+  
+  let args = window_render_args(window);
+  let mut output = create_output(settings, args.draw_width, args.draw_height)?;
+  let mut captured_frames = 0;
+  
+  replay_frames(&replay, scripts, settings.fps, to, |frame_number, state| {
+    if frame_number as f64 / settings.fps >= settings.from {
+      render(state, &args, resources, &Vec::new(), &None, &new_time_control(), gl);
+      let pixels = read_frame(args.draw_width, args.draw_height);
+      write_frame(&mut output, captured_frames, pixels, args.draw_width, args.draw_height)?;
+      captured_frames += 1;
+      
+      // show the progress
+      window.swap_buffers();
+    }
+    
+    Ok(())
+  })?;
+  
+  Ok(captured_frames)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serialization::*;
+  
+  
+  // Frames which don't line up with the capture's frames, some of them longer.
+  fn irregular_replay() -> Replay {
+    let mut replay = new_replay(&level_state(4));
+    let frame_durations = [1.0 / 60.0, 1.0 / 23.0, 1.0 / 144.0];
+    for frame in 0..200 {
+      replay.events.push(TimePasses(frame_durations[frame % frame_durations.len()]));
+      if frame % 40 == 10 {
+        replay.events.push(PressRight(0));
+        replay.events.push(ReleaseRight(0));
+      }
+    }
+    
+    replay
+  }
+  
+  #[test]
+  fn frames_show_the_recorded_session() {
+    let replay = irregular_replay();
+    let scripts = load_scripts();
+    let duration = replay_duration(&replay);
+    let fps = 50.0;
+    
+    let mut frame_times = Vec::new();
+    let final_state = replay_frames(&replay, &scripts, fps, duration, |frame_number, state| {
+      frame_times.push((frame_number as f64 / fps, state.time));
+      Ok(())
+    }).unwrap();
+    
+    assert_eq!(frame_times.len(), (duration * fps) as usize + 1);
+    for &(frame_time, state_time) in &frame_times {
+      assert!((state_time - frame_time).abs() < 1e-9, "{} {}", frame_time, state_time);
+    }
+    
+    let mut state = replay.initial_state.clone().unwrap();
+    let mut audio = NullAudio;
+    for &event in &replay.events {
+      update(&mut state, &scripts, &mut audio, event);
+    }
+    assert_eq!(state_to_binary(&final_state), state_to_binary(&state));
+  }
+  
+  #[test]
+  fn frames_between_two_events_are_interpolated() {
+    let mut replay = new_replay(&level_state(4));
+    replay.events.push(PressRight(0));
+    replay.events.push(TimePasses(0.2));
+    replay.events.push(ReleaseRight(0));
+    let scripts = load_scripts();
+    let fps = 50.0;
+    
+    let mut player_positions = Vec::new();
+    replay_frames(&replay, &scripts, fps, 0.2, |_, state| {
+      player_positions.push(compute_player_f_pos(&state.players[0].pos, state.time));
+      Ok(())
+    }).unwrap();
+    
+    // the frames 1 and 2 are both in the middle of the only TimePasses event
+    assert_eq!(player_positions.len(), 11);
+    assert!(player_positions[1] != player_positions[2], "{:?}", player_positions);
+  }
+}
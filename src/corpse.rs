@@ -3,6 +3,7 @@ use std::collections::VecDeque;
 use types::*;
 
 
+#[derive(Clone,Serialize,Deserialize)]
 pub struct Corpse {
   pub f_pos: FPos,
   pub t0: Seconds,
@@ -0,0 +1,194 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::fs::{ self, File };
+use std::io::Read;
+use std::path::{ Path, PathBuf };
+use std::time::SystemTime;
+
+use assets::*;
+use levels::*;
+use resources::*;
+use script::*;
+use settings::*;
+use state::*;
+use types::*;
+
+
+// In dev mode, the game watches the asset and level files and reloads them when they change, so artists and
+// designers can see the result of their changes without restarting the game.
+pub struct DevMode {
+  modification_times: HashMap<PathBuf, SystemTime>,
+  time_since_last_check: Seconds,
+  errors: BTreeMap<String, String>, // indexed by what we were trying to reload
+}
+
+const WATCH_PERIOD: Seconds = 0.5;
+
+// The files under those directories are loaded by load_resources.
+const RESOURCE_DIRECTORIES: &'static [&'static str] = &["images", "lang", "themes"];
+const LEVEL_DIRECTORY: &'static str = "levels";
+const SCRIPT_DIRECTORY: &'static str = "scripts";
+
+
+pub fn dev_setting() -> bool {
+  command_line_flag("dev")
+}
+
+// The watched directories are the ones the game loads its assets from, not necessarily the current directory.
+fn watched_directory(name: &str) -> PathBuf {
+  find_asset(Path::new(name)).unwrap_or(PathBuf::from(name))
+}
+
+fn scan_directory(directory: &Path, modification_times: &mut HashMap<PathBuf, SystemTime>) {
+  if let Ok(entries) = fs::read_dir(directory) {
+    for entry in entries.filter_map(|entry| entry.ok()) {
+      let path = entry.path();
+      if path.is_dir() {
+        scan_directory(&path, modification_times);
+      } else if let Ok(modification_time) = entry.metadata().and_then(|metadata| metadata.modified()) {
+        modification_times.insert(path, modification_time);
+      }
+    }
+  }
+}
+
+fn scan_watched_files() -> HashMap<PathBuf, SystemTime> {
+  let mut modification_times = HashMap::new();
+  for directory in RESOURCE_DIRECTORIES.iter().chain([LEVEL_DIRECTORY, SCRIPT_DIRECTORY].iter()) {
+    scan_directory(&watched_directory(directory), &mut modification_times);
+  }
+  
+  modification_times
+}
+
+// The errors are displayed in the game until the file is fixed, so the designers can keep playing in the meantime.
+fn report(dev_mode: &mut DevMode, what: String, result: Result<(), String>) {
+  match result {
+    Ok(())   => {
+      dev_mode.errors.remove(&what);
+    },
+    Err(err) => {
+      eprintln!("could not reload {}: {}", what, err);
+      dev_mode.errors.insert(what, err);
+    },
+  }
+}
+
+
+fn level_path(level_number: LevelNumber) -> PathBuf {
+  watched_directory(LEVEL_DIRECTORY).join(format!("level-{}.txt", level_number))
+}
+
+fn load_level_file(level_number: LevelNumber, path: &Path) -> Result<(), String> {
+  if !path.exists() {
+    set_level_override(level_number, None);
+    return Ok(());
+  }
+  
+  let mut text = String::new();
+  File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+                  .map_err(|err| err.to_string())?;
+  let level_description = parse_level_description(&text)?;
+  
+  // Keep the previous version of the level if the new one doesn't work.
+  let previous = set_level_override(level_number, Some(Box::leak(Box::new(level_description))));
+  check_level(level_number).map_err(|err| {
+    set_level_override(level_number, previous);
+    err
+  })
+}
+
+// The other levels only affect the lifetimes, but if the current level changed, its entities must be reloaded.
+fn reload_levels(dev_mode: &mut DevMode, changed_paths: &Vec<PathBuf>, state: &mut State) {
+  for level_number in min_level()..max_level()+1 {
+    let path = level_path(level_number);
+    if !changed_paths.contains(&path) {
+      continue;
+    }
+    
+    let result = load_level_file(level_number, &path);
+    let reloaded = result.is_ok();
+    report(dev_mode, path.display().to_string(), result);
+    
+    if reloaded && level_number == state.level_number {
+      state.spinies = adjust_spinies(Vec::new(), min_level() - 1, level_number);
+      state.temporary_walls = adjust_walls(Vec::new(), min_level() - 1, level_number);
+    }
+  }
+}
+
+fn reload_scripts(dev_mode: &mut DevMode, scripts: &mut Scripts) {
+  let (new_scripts, errors) = compile_scripts();
+  *scripts = new_scripts;
+  let result = if errors.is_empty() { Ok(()) } else { Err(errors.join("\n")) };
+  report(dev_mode, "scripts".to_string(), result);
+}
+
+pub fn start_dev_mode(state: &mut State, scripts: &mut Scripts) -> DevMode {
+  let modification_times = scan_watched_files();
+  let level_paths = modification_times.keys().cloned().collect();
+  
+  let mut dev_mode = DevMode {
+    modification_times: modification_times,
+    time_since_last_check: 0.0,
+    errors: BTreeMap::new(),
+  };
+  reload_levels(&mut dev_mode, &level_paths, state);
+  reload_scripts(&mut dev_mode, scripts);
+  
+  dev_mode
+}
+
+pub fn reload_changed_files(
+  dev_mode: &mut DevMode,
+  dt: Seconds,
+  resources: &mut Resources,
+  scripts: &mut Scripts,
+  state: &mut State
+) {
+  dev_mode.time_since_last_check += dt;
+  if dev_mode.time_since_last_check < WATCH_PERIOD {
+    return;
+  }
+  dev_mode.time_since_last_check = 0.0;
+  
+  // new, modified and deleted files
+  let modification_times = scan_watched_files();
+  let mut changed_paths: Vec<PathBuf> = Vec::new();
+  for (path, time) in &modification_times {
+    if dev_mode.modification_times.get(path) != Some(time) {
+      changed_paths.push(path.clone());
+    }
+  }
+  for path in dev_mode.modification_times.keys() {
+    if !modification_times.contains_key(path) {
+      changed_paths.push(path.clone());
+    }
+  }
+  dev_mode.modification_times = modification_times;
+  
+  let changed = |directory: &str| {
+    let directory = watched_directory(directory);
+    changed_paths.iter().any(|path| path.starts_with(&directory))
+  };
+  
+  if RESOURCE_DIRECTORIES.iter().any(|directory| changed(directory)) {
+    // the textures are only replaced once they have all been loaded successfully
+    let result = load_resources().map(|new_resources| {
+      *resources = new_resources;
+    });
+    report(dev_mode, "resources".to_string(), result);
+  }
+  if changed(LEVEL_DIRECTORY) {
+    reload_levels(dev_mode, &changed_paths, state);
+  }
+  if changed(SCRIPT_DIRECTORY) {
+    reload_scripts(dev_mode, scripts);
+  }
+}
+
+pub fn dev_errors(dev_mode: &Option<DevMode>) -> Vec<String> {
+  match *dev_mode {
+    Some(ref dev_mode) => dev_mode.errors.iter().map(|(what, err)| format!("{}: {}", what, err)).collect(),
+    None               => Vec::new(),
+  }
+}
@@ -1,21 +1,138 @@
 extern crate graphics;
 
+use std::collections::HashMap;
+use std::path::Path;
 use graphics::*;
 use graphics::math::*;
+use graphics::types::Color;
 use opengl_graphics::{ GlGraphics, Texture };
 
+use assets::*;
+
+
+pub type GlyphIndex = u16;
 
 pub struct Font {
   pub texture: Texture,
-  pub zeroth_char: char, pub grid_width: u8,
+  pub glyphs: HashMap<char, GlyphIndex>, pub fallback: GlyphIndex,
+  pub grid_width: GlyphIndex,
   pub cell_width:   f64, pub cell_height:   f64,
   pub sprite_width: f64, pub sprite_height: f64,
 }
 
+// Everything from the font descriptor, that is, everything but the texture.
+struct FontDescription {
+  glyphs: HashMap<char, GlyphIndex>,
+  fallback: char,
+  grid_width: GlyphIndex,
+  cell_size:   (f64, f64),
+  sprite_size: (f64, f64),
+}
+
+
+fn parse_code_point(word: &str) -> Result<char, String> {
+  if !word.starts_with("U+") {
+    return Err(format!("syntax error in font description: expected a code point, got {:?}", word));
+  }
+  
+  u32::from_str_radix(&word[2..], 16).ok()
+                                     .and_then(|code_point| ::std::char::from_u32(code_point))
+                                     .ok_or(format!("syntax error in font description: invalid code point {:?}", word))
+}
+
+fn parse_number<A: ::std::str::FromStr>(word: Option<&str>) -> Result<A, String> {
+  word.and_then(|word| word.parse().ok())
+      .ok_or("syntax error in font description: expected a number".to_string())
+}
+
+// A font descriptor looks like this:
+//
+//   # comment
+//   grid_width  25           # number of cells per row in the atlas
+//   cell_size   20 20        # size of a cell in the atlas, in pixels
+//   sprite_size 10 20        # distance between the characters when we draw them
+//   U+0000..U+00FF 0         # a range of code points, and the cell containing the first one
+//   U+00E9 233               # a single code point
+//   fallback U+003F          # the character to draw instead of the ones which aren't in the atlas
+fn parse_font_description(text: &str) -> Result<FontDescription, String> {
+  let mut glyphs = HashMap::new();
+  let mut fallback = None;
+  let mut grid_width = None;
+  let mut cell_size = None;
+  let mut sprite_size = None;
+  
+  for line in text.lines() {
+    let line = line.split('#').next().unwrap();
+    let mut words = line.split_whitespace();
+    
+    match words.next() {
+      None                => {},
+      Some("grid_width")  => grid_width  = Some(parse_number(words.next())?),
+      Some("cell_size")   => cell_size   = Some((parse_number(words.next())?, parse_number(words.next())?)),
+      Some("sprite_size") => sprite_size = Some((parse_number(words.next())?, parse_number(words.next())?)),
+      Some("fallback")    => fallback    = Some(parse_code_point(words.next().unwrap_or(""))?),
+      Some(range)         => {
+        let mut bounds = range.split("..");
+        let first = parse_code_point(bounds.next().unwrap())?;
+        let last = match bounds.next() {
+          Some(bound) => parse_code_point(bound)?,
+          None        => first,
+        };
+        let first_index: GlyphIndex = parse_number(words.next())?;
+        if last < first || (last as u32 - first as u32) > (GlyphIndex::max_value() - first_index) as u32 {
+          return Err(format!("syntax error in font description: invalid range {:?}", range));
+        }
+        
+        for code_point in first as u32 .. last as u32 + 1 {
+          if let Some(c) = ::std::char::from_u32(code_point) {
+            glyphs.insert(c, first_index + (code_point - first as u32) as GlyphIndex);
+          }
+        }
+      },
+    }
+  }
+  
+  Ok(FontDescription {
+    glyphs: glyphs,
+    fallback: fallback.ok_or("font description has no fallback")?,
+    grid_width: grid_width.ok_or("font description has no grid_width")?,
+    cell_size: cell_size.ok_or("font description has no cell_size")?,
+    sprite_size: sprite_size.ok_or("font description has no sprite_size")?,
+  })
+}
+
+// Load "foo.png" and its descriptor, "foo.txt".
+pub fn load_font(path: &Path) -> Result<Font, String> {
+  let descriptor_path = path.with_extension("txt");
+  let font_description = parse_font_description(&read_text_asset(&descriptor_path)?)
+                           .map_err(|err| format!("{}: {}", descriptor_path.display(), err))?;
+  
+  let fallback = *font_description.glyphs.get(&font_description.fallback)
+                                         .ok_or(format!("{}: the fallback character is not in the font",
+                                                        descriptor_path.display()))?;
+  let texture = load_texture_asset(path)?;
+  
+  Ok(Font {
+    texture: texture,
+    glyphs: font_description.glyphs, fallback: fallback,
+    grid_width: font_description.grid_width,
+    cell_width:   font_description.cell_size.0,   cell_height:   font_description.cell_size.1,
+    sprite_width: font_description.sprite_size.0, sprite_height: font_description.sprite_size.1,
+  })
+}
+
+fn glyph_index(font: &Font, c: char) -> GlyphIndex {
+  *font.glyphs.get(&c).unwrap_or(&font.fallback)
+}
 
 // Draw the text such that the origin is at the top-left of the text.
 // Supports multiple lines.
 pub fn draw_text(lines: &str, font: &Font, transform: Matrix2d, gl: &mut GlGraphics) {
+  draw_tinted_text(lines, font, [1.0, 1.0, 1.0, 1.0], transform, gl);
+}
+
+// The color multiplies the font's colors, so white leaves them unchanged.
+pub fn draw_tinted_text(lines: &str, font: &Font, color: Color, transform: Matrix2d, gl: &mut GlGraphics) {
   use graphics::draw_state::DrawState;
   use graphics::image::draw_many;
   use graphics::types::{ Rectangle, SourceRectangle };
@@ -23,9 +140,9 @@ pub fn draw_text(lines: &str, font: &Font, transform: Matrix2d, gl: &mut GlGraph
   for (line_index, line) in lines.lines().enumerate() {
     let rects: Vec<(Rectangle, SourceRectangle)> = line.chars().enumerate().map(
           |(char_index, c)| {
-            let sprite_index = c as u8 - font.zeroth_char as u8;
-            let x_index = sprite_index % 25;
-            let y_index = sprite_index / 25;
+            let sprite_index = glyph_index(font, c);
+            let x_index = sprite_index % font.grid_width;
+            let y_index = sprite_index / font.grid_width;
             let x_src = x_index as f64 * font.cell_width;
             let y_src = y_index as f64 * font.cell_height;
             let x_dst = char_index as f64 * font.sprite_width;
@@ -37,15 +154,15 @@ pub fn draw_text(lines: &str, font: &Font, transform: Matrix2d, gl: &mut GlGraph
           }
         ).collect();
     
-    draw_many(&rects, [1.0, 1.0, 1.0, 1.0], &font.texture, &DrawState::default(), transform, gl);
+    draw_many(&rects, color, &font.texture, &DrawState::default(), transform, gl);
   }
 }
 
 // Draw the text such that the origin is at the bottom-right of the text.
 // Only supports a single line.
-pub fn draw_text_bottom_right(line: &str, font: &Font, transform: Matrix2d, gl: &mut GlGraphics) {
-  let dx = -font.sprite_width * line.len() as f64;
+pub fn draw_text_bottom_right(line: &str, font: &Font, color: Color, transform: Matrix2d, gl: &mut GlGraphics) {
+  let dx = -font.sprite_width * line.chars().count() as f64;
   let dy = -font.sprite_height;
   
-  draw_text(line, font, transform.trans(dx, dy), gl);
+  draw_tinted_text(line, font, color, transform.trans(dx, dy), gl);
 }
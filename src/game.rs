@@ -1,81 +1,191 @@
 use std::mem;
 use graphics::math::*;
 
+use assist::*;
+use audio::*;
 use corpse::*;
 use levels::*;
+use particle::*;
 use player::*;
+use script::*;
 use spiny::*;
 use state::*;
 use types::*;
 use types::RawInputEvent::*;
 use types::AnimatedPos::*;
+use types::Message::*;
 
 
-fn should_die(player: &AnimatedPos, spinies: &Vec<MovingSpiny>, t0: Seconds, t: Seconds) -> Option<Action> {
+fn should_die(player: &AnimatedPos, player_index: PlayerIndex, spinies: &Vec<MovingSpiny>, spinies_speed: f64, t0: Seconds, t: Seconds, assists: &Assists) -> Option<Action> {
+  if assists.invulnerable {
+    return None;
+  }
+  
   let player_pos = compute_player_f_pos(player, t);
-  let player_rect = compute_f_rect(player_pos);
+  let player_rect = compute_f_rect(player_pos, assists.hitbox_margin);
   for spiny in spinies.iter() {
-    let spiny_pos = compute_spiny_f_pos(spiny, t0, t);
-    let spiny_rect = compute_f_rect(spiny_pos);
+    let spiny_pos = compute_spiny_f_pos(spiny, spinies_speed, t0, t);
+    let spiny_rect = compute_f_rect(spiny_pos, assists.hitbox_margin);
     
     if let Some(_) = overlap_rectangle(player_rect, spiny_rect) {
-      return Some(Action::Die(player_pos));
+      return Some(Action::Die(player_index, player_pos));
     }
   }
   
   None
 }
 
-fn handle_raw_input_event(state: &mut State, raw_input_event: RawInputEvent) -> Option<Action> {
+// Each player may move or die during the same frame.
+fn pass_time(state: &mut State, audio: &mut dyn Audio, dt: Seconds) -> Vec<Action> {
+  let previous_t = state.time;
+  state.time += dt;
+  let t = state.time;
+  
+  let bounces = update_spinies(&mut state.spinies, state.level_number, &state.temporary_walls, state.spinies_speed, &mut state.spinies_moving_since, &mut state.spiny_steps, previous_t, t);
+  if state.spinies_moving_since == t {
+    // the spinies are aligned with the grid, so this is the only time at which their speed can change, and at which
+    // the scripts' changes can be applied
+    state.spinies_speed = assisted_spiny_speed(&state.assists);
+    apply_spiny_changes(&mut state.spinies, mem::replace(&mut state.spiny_changes, Vec::new()));
+  }
+  if !bounces.is_empty() {
+    audio.play_sound(Sound::Bounce);
+  }
+  for bounce in bounces {
+    emit_burst(&mut state.particles, Burst::SparksB, bounce, t);
+  }
+  update_corpses(&mut state.corpses, t);
+  update_particles(&mut state.particles, t);
+  
+  let mut actions = Vec::new();
+  for (player_index, player) in state.players.iter_mut().enumerate() {
+    let player_action = update_player(player, player_index, state.level_number, &state.temporary_walls, t);
+    actions.extend(should_die(&player.pos, player_index, &state.spinies, state.spinies_speed, state.spinies_moving_since, t, &state.assists).or(player_action));
+  }
+  
+  actions
+}
+
+// The events of a player who isn't playing, e.g. the second player's keys outside of co-op mode, are ignored.
+fn handle_direction_event(players: &mut Vec<Player>, raw_input_event: RawInputEvent) {
+  let (player_index, is_pressed, dir) = match raw_input_event {
+                                          PressUp(i)      => (i, true,  UP),
+                                          PressLeft(i)    => (i, true,  LEFT),
+                                          PressDown(i)    => (i, true,  DOWN),
+                                          PressRight(i)   => (i, true,  RIGHT),
+                                          ReleaseUp(i)    => (i, false, UP),
+                                          ReleaseLeft(i)  => (i, false, LEFT),
+                                          ReleaseDown(i)  => (i, false, DOWN),
+                                          ReleaseRight(i) => (i, false, RIGHT),
+                                          _               => return,
+                                        };
+  let player = match players.get_mut(player_index) {
+                 Some(player) => player,
+                 None         => return,
+               };
+  let pressed = match dir {
+                  UP    => &mut player.up_pressed,
+                  LEFT  => &mut player.left_pressed,
+                  DOWN  => &mut player.down_pressed,
+                  _     => &mut player.right_pressed,
+                };
+  
+  if is_pressed {
+    press_direction(pressed, &mut player.buffered_dir, &mut player.most_recent_dir, dir);
+  } else {
+    release_direction(pressed);
+  }
+}
+
+fn handle_raw_input_event(state: &mut State, audio: &mut dyn Audio, raw_input_event: RawInputEvent) -> Vec<Action> {
   use types::Action::*;
   
   // Update the key statuses whether the game is paused or not, otherwise the character will keep moving
   // if the user pauses and then releases a key.
+  handle_direction_event(&mut state.players, raw_input_event);
   match raw_input_event {
-    PressUp      => press_direction(&mut state.player.up_pressed,    &mut state.player.buffered_dir, &mut state.player.most_recent_dir, UP),
-    PressLeft    => press_direction(&mut state.player.left_pressed,  &mut state.player.buffered_dir, &mut state.player.most_recent_dir, LEFT),
-    PressDown    => press_direction(&mut state.player.down_pressed,  &mut state.player.buffered_dir, &mut state.player.most_recent_dir, DOWN),
-    PressRight   => press_direction(&mut state.player.right_pressed, &mut state.player.buffered_dir, &mut state.player.most_recent_dir, RIGHT),
-    
-    ReleaseUp    => release_direction(&mut state.player.up_pressed),
-    ReleaseLeft  => release_direction(&mut state.player.left_pressed),
-    ReleaseDown  => release_direction(&mut state.player.down_pressed),
-    ReleaseRight => release_direction(&mut state.player.right_pressed),
+    // display settings, so they don't affect the game nor close the messages
+    PressMemory    => state.memory_overlay    = !state.memory_overlay,
+    PressInspector => state.inspector_overlay = !state.inspector_overlay,
     
     _ => {},
   }
   
-  match state.message {
-    Some(_) =>
+  let action = match state.message {
+    // Like a sign with choices, except that choosing changes the option instead of closing the menu.
+    Some(AssistsM) =>
       match raw_input_event {
-        PressPause | PressAnyKey | ReleaseUp | ReleaseLeft | ReleaseDown | ReleaseRight => Some(Unpause),
-        _                                                                               => None,
+        PressUp(_)               => Some(HighlightChoice((state.selected_choice + ASSIST_CHOICE_COUNT - 1) % ASSIST_CHOICE_COUNT)),
+        PressDown(_)             => Some(HighlightChoice((state.selected_choice + 1) % ASSIST_CHOICE_COUNT)),
+        PressPause | PressAnyKey => Some(ChangeAssist(state.selected_choice)),
+        PressAssists             => Some(ChangeAssist(ASSIST_DONE_CHOICE)),
+        _                        => None,
       },
+    Some(_) if raw_input_event == PressAssists => Some(OpenAssists),
+    Some(message) => {
+      let sign = sign_description(message, &state.flags);
+      let last_page = state.message_page + 1 >= sign.pages;
+      let choice_count = if last_page { sign.choices.len() as u8 } else { 0 };
+      
+      // Releasing the arrow key which walked into a plain sign closes it, but longer signs are read at the player's
+      // pace, so they only respond to the other keys. In co-op mode, either player can read on.
+      match raw_input_event {
+        PressUp(_)   if choice_count > 0 => Some(HighlightChoice((state.selected_choice + choice_count - 1) % choice_count)),
+        PressDown(_) if choice_count > 0 => Some(HighlightChoice((state.selected_choice + 1) % choice_count)),
+        PressPause | PressAnyKey         =>
+          if !last_page {
+            Some(NextPage)
+          } else if choice_count > 0 {
+            Some(Choose(sign.choices[state.selected_choice as usize]))
+          } else {
+            Some(Unpause)
+          },
+        ReleaseUp(_) | ReleaseLeft(_) | ReleaseDown(_) | ReleaseRight(_)
+          if sign.pages == 1 && sign.choices.is_empty() => Some(Unpause),
+        _                                               => None,
+      }
+    },
     None =>
       match raw_input_event {
-        TimePasses(dt) => {
-          state.time += dt;
-          let t = state.time;
-          
-          update_spinies(&mut state.spinies, state.level_number, &mut state.spinies_moving_since, t);
-          update_corpses(&mut state.corpses, t);
-          let player_action = update_player(&mut state.player, state.level_number, t);
-          
-          should_die(&state.player.pos, &state.spinies, state.spinies_moving_since, t).or(player_action)
-        },
+        TimePasses(dt) => return pass_time(state, audio, dt),
         
-        PressUp    => initiate_move(&mut state.player, state.level_number, UP   ),
-        PressLeft  => initiate_move(&mut state.player, state.level_number, LEFT ),
-        PressDown  => initiate_move(&mut state.player, state.level_number, DOWN ),
-        PressRight => initiate_move(&mut state.player, state.level_number, RIGHT),
+        PressUp(i)    => initiate_player_move(state, i, UP   ),
+        PressLeft(i)  => initiate_player_move(state, i, LEFT ),
+        PressDown(i)  => initiate_player_move(state, i, DOWN ),
+        PressRight(i) => initiate_player_move(state, i, RIGHT),
         
-        PressPause => Some(Pause),
-        _          => None,
+        PressPause   => Some(Pause),
+        PressAssists => Some(OpenAssists),
+        _            => None,
       },
-  }
+  };
+  
+  action.into_iter().collect()
+}
+
+fn initiate_player_move(state: &mut State, player_index: PlayerIndex, dir: Dir) -> Option<Action> {
+  let level_number = state.level_number;
+  let temporary_walls = &state.temporary_walls;
+  state.players.get_mut(player_index).and_then(|player| initiate_move(player, player_index, level_number, temporary_walls, dir))
+}
+
+// A level transition only happens once every player has gone through the same door, and the last one's exit
+// animation is over.
+fn all_players_moved_out(players: &Vec<Player>, level_src: LevelNumber, level_dst: LevelNumber, t: Seconds) -> bool {
+  players.iter().all(|player| match player.pos {
+    MovingOutSince(src, dst, t0) => src == level_src && dst == level_dst
+                                 && t0 + PLAYER_MOVE_DURATION + TRANSITION_EXTRA_DURATION <= t,
+    _                            => false,
+  })
 }
 
-fn execute_action(state: &mut State, action: Action) {
+// where the player is walking through the door
+fn emit_door_dust(state: &mut State, player_index: PlayerIndex) {
+  let f_pos = compute_player_f_pos(&state.players[player_index].pos, state.time);
+  emit_burst(&mut state.particles, Burst::DustB, [f_pos[0] + 0.5, f_pos[1] + 0.5], state.time);
+}
+
+fn execute_action(state: &mut State, audio: &mut dyn Audio, action: Action) {
   use types::Action::*;
   
   // you can't un-pause from the "THE END" message, you must quit using ESC
@@ -84,59 +194,97 @@ fn execute_action(state: &mut State, action: Action) {
   }
   
   match action {
-    Move(pos, dir) => {
-      state.player.buffered_dir = None;
-      state.player.pos = MovingSince(pos, dir, state.time);
+    Move(player_index, pos, dir) => {
+      state.players[player_index].buffered_dir = None;
+      state.players[player_index].pos = MovingSince(pos, dir, state.time);
+      
+      audio.play_sound(Sound::Footstep);
+    },
+    Slide(player_index, pos, dir) => {
+      // don't consume the buffered key tap, we'll honor it once the player regains control
+      state.players[player_index].pos = MovingSince(pos, dir, state.time);
+    },
+    Bump => {
+      audio.play_sound(Sound::Bump);
     },
     ReadSign(message) => {
       state.message = Some(message);
+      state.message_page = 0;
+      state.selected_choice = 0;
+      
+      audio.play_sound(Sound::Sign);
+    },
+    NextPage => {
+      state.message_page += 1;
+      
+      audio.play_sound(Sound::Sign);
+    },
+    HighlightChoice(choice_index) => {
+      state.selected_choice = choice_index;
+    },
+    Choose(flag) => {
+      state.flags.insert(flag);
+      state.message = None;
+      
+      // the arrow keys were used to pick a choice, not to walk
+      for player in &mut state.players {
+        player.buffered_dir = None;
+      }
     },
-    Die(f_pos) => {
+    Die(player_index, f_pos) => {
       let corpse = Corpse {
         f_pos: f_pos,
         t0: state.time,
       };
       state.corpses.push_back(corpse);
       
-      state.player.pos = moving_in(state.previous_level, state.level_number, state.time);
+      emit_burst(&mut state.particles, Burst::DeathB, [f_pos[0] + 0.5, f_pos[1] + 0.5], state.time);
+      state.shake_t0 = Some(state.time);
+      
+      state.players[player_index].pos = moving_in(state.previous_level, state.level_number, state.time);
+      
+      audio.play_sound(Sound::Death);
     },
     
-    PreviousLevel => {
-      let next_level = state.level_number - 1;
+    PreviousLevel(player_index) | NextLevel(player_index) => {
+      let next_level = match action {
+                         PreviousLevel(_) => state.level_number - 1,
+                         _                => state.level_number + 1,
+                       };
       
-      state.next_level = Some(next_level);
-      state.player.pos = moving_out(state.level_number, next_level, state.time);
-    },
-    NextLevel => {
-      let next_level = state.level_number + 1;
+      // in co-op mode, the other door stays closed until the players who went through the first one are followed
+      if state.next_level.map_or(false, |level| level != next_level) {
+        audio.play_sound(Sound::Bump);
+        return;
+      }
+      emit_door_dust(state, player_index);
       
       state.next_level = Some(next_level);
-      state.player.pos = moving_out(state.level_number, next_level, state.time);
+      state.players[player_index].pos = moving_out(state.level_number, next_level, state.time);
+      
+      audio.play_sound(Sound::Door);
     },
     TransitionLevel(level_src, level_dst) => {
-      if level_dst < min_level() {
+      if !all_players_moved_out(&state.players, level_src, level_dst, state.time) {
+        // wait for the other player
+      } else if level_dst < min_level() {
+        let memory_overlay = state.memory_overlay;
+        let effects = state.effects;
+        let assists = state.assists;
+        let player_count = state.players.len();
         *state = initial_state();
+        state.memory_overlay = memory_overlay;
+        state.effects = effects;
+        state.assists = assists;
+        state.spinies_speed = assisted_spiny_speed(&assists);
+        set_player_count(state, player_count);
       } else if level_dst > max_level() as LevelNumber {
-        state.message = Some(".............................................\n\
-                              .                                           .\n\
-                              .                                           .\n\
-                              .                                           .\n\
-                              .                  THE END                  .\n\
-                              .                                           .\n\
-                              .           Thank you for playing           .\n\
-                              .    \"I've Seen This Room Twice Already\"!   .\n\
-                              .                                           .\n\
-                              .     If you have enjoyed it, consider      .\n\
-                              .     playing the Ludum Dare 31 prequel,    .\n\
-                              .     \"I've Seen This Room Before\" :)       .\n\
-                              .                                           .\n\
-                              .                                           .\n\
-                              .             press esc to quit             .\n\
-                              .                                           .\n\
-                              .............................................");
+        state.message = Some(TheEndM);
         state.frozen = true;
       } else if state.time == state.spinies_moving_since { // only transition when the spinies are aligned with the grid
-        state.player.pos = moving_in(level_src, level_dst, state.time);
+        for player in &mut state.players {
+          player.pos = moving_in(level_src, level_dst, state.time);
+        }
         
         state.previous_level = level_src;
         state.level_number = level_dst;
@@ -155,32 +303,41 @@ fn execute_action(state: &mut State, action: Action) {
     },
     
     Pause => {
-      state.message = Some(".............................................\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                ** PAUSED **               .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .                                           .\n\
-                            .         press any key to continue         .\n\
-                            .                                           .\n\
-                            .............................................");
+      state.message = Some(PausedM);
+      state.message_page = 0;
+      state.selected_choice = 0;
     },
     Unpause => {
       state.message = None;
     },
+    
+    OpenAssists => {
+      state.message = Some(AssistsM);
+      state.message_page = 0;
+      state.selected_choice = 0;
+      
+      audio.play_sound(Sound::Sign);
+    },
+    ChangeAssist(choice_index) => {
+      if choice_index == ASSIST_DONE_CHOICE {
+        state.message = None;
+        
+        // the arrow keys were used to pick a choice, not to walk
+        for player in &mut state.players {
+          player.buffered_dir = None;
+        }
+      } else {
+        change_assist(&mut state.assists, choice_index);
+      }
+    },
   }
 }
 
-pub fn update(state: &mut State, raw_input_event: RawInputEvent) {
-  if let Some(action) = handle_raw_input_event(state, raw_input_event) {
-    execute_action(state, action);
+pub fn update(state: &mut State, scripts: &Scripts, audio: &mut dyn Audio, raw_input_event: RawInputEvent) {
+  for action in handle_raw_input_event(state, audio, raw_input_event) {
+    execute_action(state, audio, action.clone());
+    run_script(scripts, state, &action);
   }
+  
+  audio.play_music(music_for_level(state.level_number));
 }
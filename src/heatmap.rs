@@ -0,0 +1,57 @@
+extern crate image;
+extern crate opengl_graphics;
+extern crate piston;
+
+use opengl_graphics::GlGraphics;
+use piston::window::Window;
+
+use capture::*;
+use levels::*;
+use render::*;
+use resources::*;
+use settings::*;
+use telemetry::*;
+use types::*;
+
+
+// "--heatmap N" draws the heatmap of level N, from the sessions recorded with "--telemetry DIR", and saves it to
+// "--heatmap-output FILE" instead of playing the game. DIR defaults to "telemetry" and FILE to "heatmap-N.png".
+pub struct HeatmapSettings {
+  pub level_number: LevelNumber,
+  pub telemetry_directory: String,
+  pub output_path: String,
+}
+
+pub fn heatmap_setting() -> Result<Option<HeatmapSettings>, String> {
+  let value = match command_line_option("heatmap") {
+    Some(value) => value,
+    None        => return Ok(None),
+  };
+  let level_number: LevelNumber = value.parse().map_err(|_| format!("--heatmap expects a level number, not {:?}", value))?;
+  
+  Ok(Some(HeatmapSettings {
+    level_number: level_number,
+    telemetry_directory: telemetry_setting().unwrap_or("telemetry".to_string()),
+    output_path: command_line_option("heatmap-output").unwrap_or(format!("heatmap-{}.png", level_number)),
+  }))
+}
+
+pub fn run_heatmap<W: Window>(
+  settings: &HeatmapSettings,
+  window: &mut W,
+  resources: &Resources,
+  gl: &mut GlGraphics
+) -> Result<(), String> {
+  if settings.level_number < min_level() || settings.level_number > max_level() {
+    return Err(format!("there is no level {}", settings.level_number));
+  }
+  let telemetry = load_telemetry(&settings.telemetry_directory, settings.level_number)?;
+  
+  let args = window_render_args(window);
+  render_heatmap(settings.level_number, &telemetry, &args, resources, gl);
+  let pixels = read_frame(args.draw_width, args.draw_height);
+  window.swap_buffers();
+  
+  image::save_buffer(&settings.output_path, &pixels, args.draw_width, args.draw_height, image::ColorType::RGBA(8))
+       .map_err(|err| format!("could not write {}: {}", settings.output_path, err))
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+
+use assets::*;
+use levels::*;
+use settings::*;
+use types::*;
+
+
+pub const DEFAULT_LANGUAGE: &'static str = "en";
+
+// All the texts for one language, indexed by message key.
+pub struct Catalog {
+  texts: HashMap<String, String>,
+}
+
+
+// A catalog looks like this:
+//
+//   # comment
+//   [title-screen-title]
+//   I've Seen This Room Twice Already
+//
+//   [level-1-sign-0]
+//   Can you figure out what the numbers mean?
+//
+// Each text extends until the next key. Blank lines inside a text are kept, trailing blank lines are not.
+fn parse_catalog(text: &str) -> Result<HashMap<String, String>, String> {
+  let mut texts = HashMap::new();
+  let mut key: Option<String> = None;
+  let mut lines: Vec<&str> = Vec::new();
+  
+  {
+    let mut flush = |key: &Option<String>, lines: &mut Vec<&str>| {
+      while lines.last() == Some(&"") {
+        lines.pop();
+      }
+      if let Some(ref key) = *key {
+        texts.insert(key.clone(), lines.join("\n"));
+      } else if !lines.is_empty() {
+        return Err("syntax error in catalog: text before the first key".to_string());
+      }
+      lines.clear();
+      Ok(())
+    };
+    
+    for line in text.lines() {
+      let line = line.trim_end();
+      if line.starts_with('#') {
+        continue;
+      }
+      
+      if line.starts_with('[') && line.ends_with(']') {
+        flush(&key, &mut lines)?;
+        key = Some(line[1..line.len()-1].to_string());
+      } else {
+        lines.push(line);
+      }
+    }
+    flush(&key, &mut lines)?;
+  }
+  
+  Ok(texts)
+}
+
+fn read_catalog(language: &str) -> Option<HashMap<String, String>> {
+  let path = Path::new("lang").join(format!("{}.txt", language));
+  let result = read_text_asset(&path).and_then(|text| {
+    parse_catalog(&text).map_err(|err| format!("{}: {}", path.display(), err))
+  });
+  match result {
+    Ok(texts) => Some(texts),
+    Err(err)  => {
+      eprintln!("{}", err);
+      None
+    },
+  }
+}
+
+// Texts which haven't been translated yet are displayed in the default language.
+pub fn load_catalog(language: &str) -> Catalog {
+  let mut texts = read_catalog(DEFAULT_LANGUAGE).unwrap_or(HashMap::new());
+  if language != DEFAULT_LANGUAGE {
+    if let Some(translations) = read_catalog(language) {
+      texts.extend(translations);
+    }
+  }
+  
+  Catalog {
+    texts: texts,
+  }
+}
+
+
+// "fr_CA.UTF-8" => "fr"
+fn language_from_locale(locale: &str) -> Option<String> {
+  let language: String = locale.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+  if language.is_empty() || language == "C" || language == "POSIX" {
+    None
+  } else {
+    Some(language.to_lowercase())
+  }
+}
+
+// The language can be chosen with "--lang fr", otherwise we use the system locale.
+pub fn language_setting() -> String {
+  if let Some(language) = command_line_option("lang") {
+    return language;
+  }
+  
+  // same precedence as gettext
+  ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+                                   .filter_map(|name| env::var(name).ok())
+                                   .filter(|locale| !locale.is_empty())
+                                   .next()
+                                   .and_then(|locale| language_from_locale(&locale))
+                                   .unwrap_or(DEFAULT_LANGUAGE.to_string())
+}
+
+
+pub fn message_key(message: Message) -> String {
+  use types::Message::*;
+  
+  match message {
+    TitleM                          => "title-screen".to_string(),
+    PausedM                         => "paused".to_string(),
+    TheEndM                         => "the-end".to_string(),
+    AssistsM                        => "assists".to_string(),
+    SignM(level_number, sign_index) => format!("level-{}-sign-{}", level_number, sign_index),
+    ScriptM(key)                    => key.to_string(),
+  }
+}
+
+// The first page uses the message's key, e.g. "level-4-sign-0", and the others add a suffix, e.g.
+// "level-4-sign-0-page-2". Descriptions which depend on a flag also add it, e.g. "level-6-sign-0-if-some-flag".
+pub fn page_key(message: Message, sign: &SignDescription, page: u8) -> String {
+  let key = match sign.flag {
+              Some(flag) => format!("{}-if-{}", message_key(message), flag),
+              None       => message_key(message),
+            };
+  
+  if page == 0 {
+    key
+  } else {
+    format!("{}-page-{}", key, page + 1)
+  }
+}
+
+pub fn choice_key(flag: Flag) -> String {
+  format!("choice-{}", flag)
+}
+
+// Falls back to the key itself, so scripts can also display text which isn't in the catalog.
+pub fn catalog_text(catalog: &Catalog, key: &str) -> String {
+  match catalog.texts.get(key) {
+    Some(text) => text.clone(),
+    None       => key.to_string(),
+  }
+}
+
+pub fn message_text(catalog: &Catalog, message: Message) -> String {
+  catalog_text(catalog, &message_key(message))
+}
@@ -0,0 +1,53 @@
+use settings::*;
+use state::*;
+use types::*;
+use types::AnimatedPos::*;
+
+
+pub fn dir_name(dir: Dir) -> &'static str {
+  match dir {
+    UP    => "up",
+    LEFT  => "left",
+    DOWN  => "down",
+    RIGHT => "right",
+    _     => "nowhere",
+  }
+}
+
+fn optional_dir_name(dir: Option<Dir>) -> &'static str {
+  dir.map_or("none", dir_name)
+}
+
+pub fn describe_player(player_pos: &AnimatedPos) -> String {
+  match *player_pos {
+    Idle(pos)                                => format!("idle at {:?}", pos),
+    MovingSince(pos, dir, t0)                => format!("moving {} from {:?} since {:.3}", dir_name(dir), pos, t0),
+    MovingOutSince(level_src, level_dst, t0) => format!("leaving level {} for level {} since {:.3}", level_src, level_dst, t0),
+    MovingInUntil(level_src, level_dst, t)   => format!("entering level {} from level {} until {:.3}", level_dst, level_src, t),
+  }
+}
+
+// What the debug overlay displays, two lines per player and one line per spiny.
+pub fn inspector_lines(state: &State) -> Vec<String> {
+  let mut lines = vec![format!("t={:.3}, spinies moving since {:.3}", state.time, state.spinies_moving_since)];
+  for (player_index, player) in state.players.iter().enumerate() {
+    // "player 2" is only needed in co-op mode
+    let name = if state.players.len() > 1 { format!("player {}", player_index + 1) } else { "player".to_string() };
+    lines.push(format!("{} {}", name, describe_player(&player.pos)));
+    lines.push(format!("buffered {}, most recent {}", optional_dir_name(player.buffered_dir), optional_dir_name(player.most_recent_dir)));
+  }
+  for spiny in &state.spinies {
+    lines.push(format!("spiny {:?} {} {}-{}{}",
+                       spiny.pos, dir_name(spiny.dir),
+                       spiny.lifetime.level_min, spiny.lifetime.level_max,
+                       if spiny.enabled { "" } else { " disabled" }));
+  }
+  
+  lines
+}
+
+
+// F4 dumps the state to "--dump FILE", or to "state.json" by default, see save_state.
+pub fn dump_setting() -> String {
+  command_line_option("dump").unwrap_or("state.json".to_string())
+}
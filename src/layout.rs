@@ -0,0 +1,280 @@
+use assist::*;
+use i18n::*;
+use levels::*;
+use state::*;
+use types::*;
+
+
+// What a message looks like once we know the language, but before we know how wide the window is.
+pub struct MessageBox {
+  pub title: Option<String>,
+  pub body: String, // plain prose, paragraphs are separated by blank lines
+  pub choices: Vec<String>,
+  pub selected_choice: usize,
+  pub footer: Option<String>,
+}
+
+// The frame is one dot plus one space of padding on each side.
+const FRAME_WIDTH: usize = 2;
+
+
+pub fn message_box(catalog: &Catalog, state: &State, message: Message) -> MessageBox {
+  use types::Message::*;
+  
+  let text = |key: &str| catalog_text(catalog, key);
+  
+  // the choices show the current value of each option
+  if message == AssistsM {
+    return MessageBox {
+      title: Some(text("assists-title")),
+      body: message_text(catalog, message),
+      choices: assist_choices(catalog, &state.assists),
+      selected_choice: state.selected_choice as usize,
+      footer: Some(text("press-space-to-change")),
+    };
+  }
+  
+  let sign = sign_description(message, &state.flags);
+  let page = state.message_page.min(sign.pages - 1);
+  let last_page = page + 1 >= sign.pages;
+  let choices: Vec<String> = if last_page {
+                               sign.choices.iter().map(|&flag| text(&choice_key(flag))).collect()
+                             } else {
+                               Vec::new()
+                             };
+  
+  let (title, body, footer) = match message {
+    TitleM  => (Some(text("title-screen-title")), message_text(catalog, message), Some(text("press-any-key-to-begin"))),
+    PausedM => (Some(message_text(catalog, message)), format!("{}\n\n{}", text("memory-overlay-hint"), text("assists-hint")), Some(text("press-any-key-to-continue"))),
+    TheEndM => (Some(text("the-end-title")), message_text(catalog, message), Some(text("press-esc-to-quit"))),
+    _       => {
+      let footer = if !last_page {
+                     Some(text("press-space-for-next-page"))
+                   } else if !choices.is_empty() {
+                     Some(text("press-space-to-choose"))
+                   } else {
+                     None
+                   };
+      
+      (None, text(&page_key(message, sign, page)), footer)
+    },
+  };
+  
+  MessageBox {
+    title: title,
+    body: body,
+    choices: choices,
+    selected_choice: state.selected_choice as usize,
+    footer: footer,
+  }
+}
+
+
+// Break the prose into lines of at most `columns` characters. Words which are too long for a single line are split.
+pub fn wrap_text(text: &str, columns: usize) -> Vec<String> {
+  let columns = columns.max(1);
+  let mut lines = Vec::new();
+  
+  for (paragraph_index, paragraph) in text.split("\n\n").enumerate() {
+    if paragraph_index > 0 {
+      lines.push(String::new());
+    }
+    
+    let mut line = String::new();
+    // non-breaking spaces are part of the word, e.g. "« One Room »" in French
+    for word in paragraph.split(|c| c == ' ' || c == '\n').filter(|word| !word.is_empty()) {
+      let mut word: Vec<char> = word.chars().collect();
+      loop {
+        let line_length = line.chars().count();
+        let separator = if line_length == 0 { 0 } else { 1 };
+        
+        if line_length + separator + word.len() <= columns {
+          if separator == 1 {
+            line.push(' ');
+          }
+          line.extend(word.iter());
+          break;
+        } else if line_length > 0 {
+          lines.push(line);
+          line = String::new();
+        } else {
+          let rest = word.split_off(columns);
+          lines.push(word.into_iter().collect());
+          word = rest;
+        }
+      }
+    }
+    if !line.is_empty() {
+      lines.push(line);
+    }
+  }
+  
+  lines
+}
+
+fn centered_line(line: &str, columns: usize) -> String {
+  let length = line.chars().count();
+  let left = columns.saturating_sub(length) / 2;
+  
+  format!("{}{}", " ".repeat(left), line)
+}
+
+// The lines of text making up the framed box, including the frame. The box is as narrow as the text allows, but
+// never wider than `max_columns`.
+pub fn layout_message_box(message_box: &MessageBox, max_columns: usize) -> Vec<String> {
+  let max_inner_columns = max_columns.saturating_sub(2*FRAME_WIDTH);
+  
+  let title_lines = message_box.title.as_ref().map(|title| wrap_text(title, max_inner_columns)).unwrap_or(Vec::new());
+  let body_lines = wrap_text(&message_box.body, max_inner_columns);
+  
+  // the selected choice is marked with an arrow, and the other lines are indented to match
+  let mut choice_lines: Vec<String> = Vec::new();
+  for (choice_index, choice) in message_box.choices.iter().enumerate() {
+    for (line_index, line) in wrap_text(choice, max_inner_columns.saturating_sub(2)).into_iter().enumerate() {
+      let prefix = if line_index == 0 && choice_index == message_box.selected_choice { "> " } else { "  " };
+      choice_lines.push(format!("{}{}", prefix, line));
+    }
+  }
+  let footer_lines = message_box.footer.as_ref().map(|footer| wrap_text(footer, max_inner_columns)).unwrap_or(Vec::new());
+  
+  let inner_columns = title_lines.iter()
+                                 .chain(body_lines.iter())
+                                 .chain(choice_lines.iter())
+                                 .chain(footer_lines.iter())
+                                 .map(|line| line.chars().count())
+                                 .max()
+                                 .unwrap_or(0);
+  
+  // the title and footer are centered, and separated from the body by a blank line
+  let mut inner_lines: Vec<String> = Vec::new();
+  for line in &title_lines {
+    inner_lines.push(centered_line(line, inner_columns));
+  }
+  if !title_lines.is_empty() && !body_lines.is_empty() {
+    inner_lines.push(String::new());
+  }
+  inner_lines.extend(body_lines.iter().cloned());
+  if !choice_lines.is_empty() && !inner_lines.is_empty() {
+    inner_lines.push(String::new());
+  }
+  inner_lines.extend(choice_lines.into_iter());
+  if !footer_lines.is_empty() && !inner_lines.is_empty() {
+    inner_lines.push(String::new());
+  }
+  for line in &footer_lines {
+    inner_lines.push(centered_line(line, inner_columns));
+  }
+  
+  let border = ".".repeat(inner_columns + 2*FRAME_WIDTH);
+  let padding = format!(".{}.", " ".repeat(inner_columns + 2*FRAME_WIDTH - 2));
+  
+  let mut lines = vec![border.clone(), padding.clone()];
+  for line in inner_lines {
+    let right = inner_columns - line.chars().count();
+    lines.push(format!(". {}{} .", line, " ".repeat(right)));
+  }
+  lines.push(padding);
+  lines.push(border);
+  
+  lines
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  
+  fn plain_box(title: Option<&str>, body: &str) -> MessageBox {
+    MessageBox {
+      title: title.map(|title| title.to_string()),
+      body: body.to_string(),
+      choices: Vec::new(),
+      selected_choice: 0,
+      footer: None,
+    }
+  }
+  
+  
+  #[test]
+  fn words_wrap_at_the_last_space() {
+    assert_eq!(wrap_text("the quick brown fox jumps", 10), vec!["the quick", "brown fox", "jumps"]);
+    assert_eq!(wrap_text("exactly ten", 11), vec!["exactly ten"]);
+  }
+  
+  #[test]
+  fn long_words_are_split() {
+    assert_eq!(wrap_text("a supercalifragilistic word", 8), vec!["a", "supercal", "ifragili", "stic", "word"]);
+    assert_eq!(wrap_text("ünicödé", 3), vec!["üni", "cöd", "é"]);
+  }
+  
+  #[test]
+  fn blank_lines_separate_paragraphs() {
+    // a single newline is just a space, the texts are prose
+    assert_eq!(wrap_text("one\ntwo\n\nthree", 20), vec!["one two", "", "three"]);
+    assert_eq!(wrap_text("non\u{a0}breaking space", 12), vec!["non\u{a0}breaking", "space"]);
+  }
+  
+  #[test]
+  fn empty_texts_have_no_lines() {
+    assert!(wrap_text("", 10).is_empty());
+    assert_eq!(layout_message_box(&plain_box(None, ""), 40), vec![
+      "....",
+      ".  .",
+      ".  .",
+      "....",
+    ]);
+  }
+  
+  #[test]
+  fn boxes_fit_their_text() {
+    assert_eq!(layout_message_box(&plain_box(None, "Now you know!"), 40), vec![
+      ".................",
+      ".               .",
+      ". Now you know! .",
+      ".               .",
+      ".................",
+    ]);
+  }
+  
+  #[test]
+  fn titles_are_centered_above_the_body() {
+    assert_eq!(layout_message_box(&plain_box(Some("THE END"), "Thank you for playing!"), 40), vec![
+      "..........................",
+      ".                        .",
+      ".        THE END         .",
+      ".                        .",
+      ". Thank you for playing! .",
+      ".                        .",
+      "..........................",
+    ]);
+  }
+  
+  #[test]
+  fn boxes_are_never_wider_than_the_window() {
+    let message_box = MessageBox {
+      title: Some("A rather long title".to_string()),
+      body: "Some text which doesn't fit on one line.".to_string(),
+      choices: vec!["Yes".to_string(), "No, not at all".to_string()],
+      selected_choice: 1,
+      footer: Some("press space".to_string()),
+    };
+    assert_eq!(layout_message_box(&message_box, 20), vec![
+      "....................",
+      ".                  .",
+      ".  A rather long   .",
+      ".      title       .",
+      ".                  .",
+      ". Some text which  .",
+      ". doesn't fit on   .",
+      ". one line.        .",
+      ".                  .",
+      ".   Yes            .",
+      ". > No, not at all .",
+      ".                  .",
+      ".   press space    .",
+      ".                  .",
+      "....................",
+    ]);
+  }
+}
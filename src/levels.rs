@@ -1,20 +1,51 @@
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::collections::{ HashMap, HashSet };
 
+use spiny::*;
 use types::*;
 
 
 pub struct LevelDescription {
   ascii_map: &'static str,
-  signs: &'static [Message],
+  signs: &'static [SignDescription],
+  spawners: &'static [SpinySpawner],
+}
+
+// Signs have a single page and no choices unless they are described otherwise. The text of each page and of each
+// choice is in the catalogs.
+pub struct SignDescription {
+  pub sign_index: u8, // "S0" is sign 0
+  pub flag: Option<Flag>, // once the player has made that choice, this description replaces the earlier ones
+  pub pages: u8,
+  pub choices: &'static [Flag], // offered on the last page, picking one sets its flag
+}
+
+const PLAIN_SIGN: SignDescription = SignDescription {
+  sign_index: 0,
+  flag: None,
+  pages: 1,
+  choices: &[],
+};
+
+// Emits a new spiny moving in the given direction every `period` spiny steps. The spiny appears in front of the
+// spawner, not on it: spawners are obstacles.
+#[derive(PartialEq,Eq,Clone,Copy)]
+pub struct SpinySpawner {
+  pub dir: Dir,
+  pub period: u8,
 }
 
 // Static information about a cell obtained straight from the level description
 #[derive(PartialEq,Eq)]
 pub enum Cell {
   FloorC,
+  IceC,
+  ConveyorC(Dir),
   LeftDoorC,
   RightDoorC,
   SignC(Message),
+  SinkC,
+  SpawnerC(SpinySpawner),
   SpinyC(Dir),
   WallC,
 }
@@ -48,8 +79,9 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##              ##.\
                 .##              RD.\
                 .##################.",
-    signs: &[                                   //v-- last char
-      "Can you figure out what the numbers mean?"
+    signs: &[
+    ],
+    spawners: &[
     ],
   },
   
@@ -63,8 +95,9 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##              ##.\
                 .##              RD.\
                 .##################.",
-    signs: &[                                   //v-- last char
-      "How about now?"
+    signs: &[
+    ],
+    spawners: &[
     ],
   },
   
@@ -78,9 +111,9 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##          ##  ##.\
                 .##              RD.\
                 .##################.",
-    signs: &[                                   //v-- last char
-      "You should be able to guess what's going to\n\
-       happen to that corner block by now."
+    signs: &[
+    ],
+    spawners: &[
     ],
   },
   
@@ -94,13 +127,10 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##      ^^  ^^  ##.\
                 .##S0^^  ^^  ^^  RD.\
                 .##################.",
-    signs: &[                                   //v-- last char
-      "I know, it's hard to read the numbers on the\n\
-       spinies when they move so fast. But now that\n\
-       you've stopped to read a sign, it's a lot\n\
-       easier isn't it :)",
-      "If there is no sign around, just press SPACE\n\
-       or P to pause the game."
+    signs: &[
+      SignDescription { sign_index: 0, flag: None, pages: 2, choices: &[] },
+    ],
+    spawners: &[
     ],
   },
   
@@ -114,8 +144,9 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##      ^^  ##  ##.\
                 .##  ^^  ##  ##  RD.\
                 .##################.",
-    signs: &[                                   //v-- last char
-      "Now you know!"
+    signs: &[
+    ],
+    spawners: &[
     ],
   },
   
@@ -130,7 +161,8 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##              RD.\
                 .##################.",
     signs: &[
-      "Almost there..."
+    ],
+    spawners: &[
     ],
   },
   
@@ -146,6 +178,8 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##################.",
     signs: &[
     ],
+    spawners: &[
+    ],
   },
   
   // LEVEL 10
@@ -160,6 +194,8 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##################.",
     signs: &[
     ],
+    spawners: &[
+    ],
   },
   
   // LEVEL 11
@@ -173,7 +209,8 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##              RD.\
                 .##################.",
     signs: &[
-      "Same thing, with only one slight difference..."
+    ],
+    spawners: &[
     ],
   },
   
@@ -189,6 +226,8 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##################.",
     signs: &[
     ],
+    spawners: &[
+    ],
   },
   
   // LEVEL 13
@@ -203,6 +242,8 @@ pub const LEVELS: &'static [LevelDescription; 11] = &[
                 .##################.",
     signs: &[
     ],
+    spawners: &[
+    ],
   },
 ];
 
@@ -220,15 +261,191 @@ const CELL_WIDTH: usize = 2;
 const ASCII_MAP_WIDTH: usize = DOT_WIDTH + (LEVEL_WIDTH as usize)*CELL_WIDTH + DOT_WIDTH;
 const ASCII_MAP_OFFSET: usize = ASCII_MAP_WIDTH + DOT_WIDTH;
 
+thread_local! {
+  // In dev mode, the levels can be edited in "levels/level-N.txt" files, which replace the descriptions above.
+  static LEVEL_OVERRIDES: RefCell<HashMap<LevelNumber, &'static LevelDescription>> = RefCell::new(HashMap::new());
+}
+
 fn get_level_description(level_number: LevelNumber) -> &'static LevelDescription {
+  if let Some(level_description) = LEVEL_OVERRIDES.with(|overrides| overrides.borrow().get(&level_number).cloned()) {
+    return level_description;
+  }
+  
   // LEVELS is 0-based, but level numbers are 1-based
   let level_index = level_number as usize - 1;
   
   &LEVELS[level_index]
 }
 
-fn message_at(level_number: LevelNumber, message_index: u8) -> Message {
-  get_level_description(level_number).signs.iter().nth(message_index as usize).unwrap()
+// Returns the previous override, so it can be restored if the new description turns out to be invalid.
+pub fn set_level_override(
+  level_number: LevelNumber,
+  level_description: Option<&'static LevelDescription>
+) -> Option<&'static LevelDescription> {
+  LEVEL_OVERRIDES.with(|overrides| {
+    let mut overrides = overrides.borrow_mut();
+    match level_description {
+      Some(level_description) => overrides.insert(level_number, level_description),
+      None                    => overrides.remove(&level_number),
+    }
+  })
+}
+
+fn parse_dir(word: Option<&str>) -> Result<Dir, String> {
+  match word {
+    Some("^") => Ok(UP),
+    Some("<") => Ok(LEFT),
+    Some("v") => Ok(DOWN),
+    Some(">") => Ok(RIGHT),
+    _         => Err("syntax error in level description: expected one of ^ < v >".to_string()),
+  }
+}
+
+fn parse_number<A: ::std::str::FromStr>(word: Option<&str>) -> Result<A, String> {
+  word.and_then(|word| word.parse().ok())
+      .ok_or("syntax error in level description: expected a number".to_string())
+}
+
+// Leaked, because the level descriptions are normally static. This is only used in dev mode, so we don't mind leaking
+// a few strings each time a level is edited.
+fn leak<A>(vec: Vec<A>) -> &'static [A] {
+  Box::leak(vec.into_boxed_slice())
+}
+
+fn leak_str(word: &str) -> &'static str {
+  Box::leak(word.to_string().into_boxed_str())
+}
+
+// A level file contains the ascii map, as in LEVELS, followed by the signs and spawners:
+//
+//    . . . . . . . . . .
+//   .##################.
+//   .LD      G0      ##.
+//   ...
+//   .##################.
+//   // comment
+//   sign 0 pages 2
+//   sign 0 if some-flag pages 1 choices some-other-flag yet-another-flag
+//   spawner > 4   // G0, pointing right, emits a spiny every 4 steps
+//
+// The spawners are numbered in order, like the entries of the `spawners` field.
+pub fn parse_level_description(text: &str) -> Result<LevelDescription, String> {
+  let mut ascii_map = String::new();
+  let mut signs = Vec::new();
+  let mut spawners = Vec::new();
+  
+  for line in text.lines() {
+    let line = line.trim_end();
+    if line.starts_with(' ') || line.starts_with('.') {
+      ascii_map.push_str(line);
+      continue;
+    }
+    
+    let line = line.split("//").next().unwrap();
+    let mut words = line.split_whitespace();
+    match words.next() {
+      None            => {},
+      Some("sign")    => {
+        let sign_index = parse_number(words.next())?;
+        let mut flag = None;
+        let mut pages = 1;
+        let mut choices = Vec::new();
+        while let Some(word) = words.next() {
+          match word {
+            "if"      => flag = Some(leak_str(words.next().ok_or("syntax error in level description: expected a flag")?)),
+            "pages"   => pages = parse_number(words.next())?,
+            "choices" => choices.extend(words.by_ref().map(leak_str)),
+            _         => return Err(format!("syntax error in level description: unexpected {:?}", word)),
+          }
+        }
+        
+        signs.push(
+          SignDescription {
+            sign_index: sign_index,
+            flag: flag,
+            pages: pages,
+            choices: leak(choices),
+          }
+        );
+      },
+      Some("spawner") => {
+        let dir = parse_dir(words.next())?;
+        let period = parse_number(words.next())?;
+        if period == 0 {
+          return Err("syntax error in level description: a spawner's period must be at least 1".to_string());
+        }
+        spawners.push(
+          SpinySpawner {
+            dir: dir,
+            period: period,
+          }
+        );
+      },
+      Some(word)      => return Err(format!("syntax error in level description: unexpected {:?}", word)),
+    }
+  }
+  
+  if ascii_map.len() != ASCII_MAP_WIDTH * (LEVEL_HEIGHT as usize + 1) {
+    return Err(format!("syntax error in level description: the ascii map should have {} lines of {} characters",
+                       LEVEL_HEIGHT + 1, ASCII_MAP_WIDTH));
+  }
+  
+  Ok(LevelDescription {
+    ascii_map: leak_str(&ascii_map),
+    signs: leak(signs),
+    spawners: leak(spawners),
+  })
+}
+
+// cell_at only parses the cells when we look at them, so look at all of them now to find the syntax errors early.
+pub fn check_level(level_number: LevelNumber) -> Result<(), String> {
+  for j in 0..LEVEL_HEIGHT {
+    for i in 0..LEVEL_WIDTH {
+      parse_cell(level_number, [i,j])?;
+    }
+  }
+  
+  Ok(())
+}
+
+pub fn has_sign(level_number: LevelNumber, sign_index: u8) -> bool {
+  use self::Cell::*;
+  use types::Message::*;
+  
+  (0..LEVEL_HEIGHT).any(|j| {
+    (0..LEVEL_WIDTH).any(|i| cell_at(level_number, [i,j]) == SignC(SignM(level_number, sign_index)))
+  })
+}
+
+// Messages which aren't signs behave like plain signs.
+pub fn sign_description(message: Message, flags: &HashSet<Flag>) -> &'static SignDescription {
+  match message {
+    Message::SignM(level_number, sign_index) =>
+      get_level_description(level_number).signs.iter()
+                                          .filter(|sign| sign.sign_index == sign_index)
+                                          .filter(|sign| sign.flag.map(|flag| flags.contains(flag)).unwrap_or(true))
+                                          .last()
+                                          .unwrap_or(&PLAIN_SIGN),
+    _ => &PLAIN_SIGN,
+  }
+}
+
+// Flags are only set by the choices offered by the signs, so we can find a flag from its name, e.g. in a saved game.
+pub fn find_flag(name: &str) -> Option<Flag> {
+  for level_number in min_level()..max_level()+1 {
+    for sign in get_level_description(level_number).signs {
+      if let Some(&flag) = sign.choices.iter().find(|&&flag| flag == name) {
+        return Some(flag);
+      }
+    }
+  }
+  
+  None
+}
+
+fn spawner_at(level_number: LevelNumber, spawner_index: u8) -> Result<SpinySpawner, String> {
+  get_level_description(level_number).spawners.iter().nth(spawner_index as usize).cloned()
+    .ok_or(format!("syntax error in level description: there is no spawner {}", spawner_index))
 }
 
 fn code_at(level_number: LevelNumber, pos: Pos) -> (u8, u8) {
@@ -247,34 +464,78 @@ fn code_at(level_number: LevelNumber, pos: Pos) -> (u8, u8) {
   }
 }
 
+// The two characters describing the cell in the ascii map, e.g. "S0".
+pub fn ascii_code_at(level_number: LevelNumber, pos: Pos) -> String {
+  let (u1,u2) = code_at(level_number, pos);
+  
+  format!("{}{}", u1 as char, u2 as char)
+}
+
+// The levels are checked when they are loaded, see check_level, so this only panics if a shipped level is invalid.
 pub fn cell_at(level_number: LevelNumber, pos: Pos) -> Cell {
+  parse_cell(level_number, pos).unwrap()
+}
+
+fn parse_cell(level_number: LevelNumber, pos: Pos) -> Result<Cell, String> {
   use self::Cell::*;
+  use types::Message::*;
   
   if (pos[0] < 0) || (pos[1] < 0) || (pos[0] >= LEVEL_WIDTH) || (pos[1] >= LEVEL_HEIGHT) {
-    FloorC
+    Ok(FloorC)
   } else {
     let (u1,u2) = code_at(level_number, pos);
     
     // The second character is sometimes a number, e.g. "S0" and "S1" to distinguish two signs
-    // in the same level, or "K2" for a key which is valid for levels CURRENT to (CURRENT+2).
-    let number = || u2 - '0' as u8;
+    // or "G0" and "G1" to distinguish two spawners in the same level, or "K2" for a key which is valid for levels CURRENT to (CURRENT+2).
+    // It can also be a direction, e.g. "C>" for a conveyor pushing to the right.
+    let syntax_error = || format!("syntax error in level description: unexpected {:?} at {:?}",
+                                  format!("{}{}", u1 as char, u2 as char), pos);
+    let number = || if (u2 as char).is_ascii_digit() { Ok(u2 - '0' as u8) } else { Err(syntax_error()) };
+    let dir = || match u2 as char {
+      '^' => Ok(UP),
+      '<' => Ok(LEFT),
+      'v' => Ok(DOWN),
+      '>' => Ok(RIGHT),
+      _   => Err(syntax_error()),
+    };
     
     match u1 as char {
-      ' ' => FloorC,
-      'L' => LeftDoorC,
-      'R' => RightDoorC,
-      'S' => SignC(message_at(level_number, number())),
-      '^' => SpinyC(UP),
-      '<' => SpinyC(LEFT),
-      'v' => SpinyC(DOWN),
-      '>' => SpinyC(RIGHT),
-      '#' => WallC,
-      _   => panic!("syntax error in level description"),
+      ' ' => Ok(FloorC),
+      '~' => Ok(IceC),
+      'C' => Ok(ConveyorC(dir()?)),
+      'L' => Ok(LeftDoorC),
+      'R' => Ok(RightDoorC),
+      'S' => Ok(SignC(SignM(level_number, number()?))),
+      'G' => Ok(SpawnerC(spawner_at(level_number, number()?)?)),
+      'O' => Ok(SinkC),
+      '^' => Ok(SpinyC(UP)),
+      '<' => Ok(SpinyC(LEFT)),
+      'v' => Ok(SpinyC(DOWN)),
+      '>' => Ok(SpinyC(RIGHT)),
+      '#' => Ok(WallC),
+      _   => Err(syntax_error()),
+    }
+  }
+}
+
+// The level description only tells us where the temporary walls are when we enter the level, but scripts can add
+// and remove some of them afterwards, so obstacles must be looked up using this function instead of cell_at.
+pub fn current_cell_at(level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, pos: Pos) -> Cell {
+  use self::Cell::*;
+  
+  if !is_inside_room(pos) {
+    cell_at(level_number, pos)
+  } else if temporary_walls.iter().any(|temporary_wall| temporary_wall.pos == pos) {
+    WallC
+  } else {
+    match cell_at(level_number, pos) {
+      WallC => FloorC,
+      cell  => cell,
     }
   }
 }
 
-fn lifetime_at(level_number: LevelNumber, pos: Pos) -> Lifetime {
+pub fn lifetime_at(level_number: LevelNumber, pos: Pos) -> Lifetime {
   let expected_cell = cell_at(level_number, pos);
   
   let mut level_min = level_number;
@@ -428,19 +689,7 @@ fn is_wall(entity: Entity) -> Option<TemporaryWall> {
 
 pub fn adjust_spinies(existing_spinies: Vec<MovingSpiny>, level_src: LevelNumber, level_dst: LevelNumber) -> Vec<MovingSpiny> {
   let mut spinies = adjust_entities(&lifetime_of_spiny, &is_spiny, existing_spinies, level_src, level_dst);
-  
-  // temporarily disable some spinies if they occupy the same cell
-  {
-    let mut occupied = HashSet::new();
-    for spiny in spinies.iter_mut() {
-      if occupied.contains(&spiny.pos) {
-        spiny.enabled = false;
-      } else {
-        occupied.insert(&spiny.pos);
-        spiny.enabled = true;
-      }
-    }
-  }
+  disable_overlapping_spinies(&mut spinies);
   
   spinies
 }
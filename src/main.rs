@@ -1,33 +1,89 @@
+extern crate bincode;
+extern crate gif;
 extern crate gl;
 extern crate glutin_window;
 extern crate graphics;
+extern crate image;
 extern crate opengl_graphics;
 extern crate piston;
+extern crate rhai;
+extern crate rodio;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate termion;
 
+use std::process;
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{ GlGraphics, OpenGL };
 use piston::event_loop::*;
 use piston::window::WindowSettings;
 
+mod animation;
+mod assist;
+mod assets;
+mod audio;
+mod capture;
 mod corpse;
+mod dev;
 mod font;
 mod game;
+mod heatmap;
+mod i18n;
+mod inspector;
+mod layout;
 mod levels;
+mod netplay;
+mod particle;
 mod player;
 mod render;
+mod replay;
 mod resources;
+#[cfg(test)]
+mod scenario;
+mod script;
+mod serialization;
+mod settings;
 mod spiny;
 mod state;
+mod telemetry;
+mod terminal;
+mod time_control;
+mod theme;
 mod types;
 
+use audio::*;
+use capture::*;
+use dev::*;
 use game::*;
+use heatmap::*;
+use inspector::*;
 use levels::*;
+use netplay::*;
+use particle::*;
+use player::*;
 use render::*;
+use replay::*;
 use resources::*;
+use script::*;
+use serialization::*;
 use state::*;
+use telemetry::*;
+use terminal::*;
+use time_control::*;
 
 
 fn main() {
+  // no window, no OpenGL, no sound
+  if terminal_setting() {
+    if let Err(err) = run_terminal(&load_scripts()) {
+      eprintln!("{}", err);
+      process::exit(1);
+    }
+    return;
+  }
+  
   // Change this to OpenGL::V2_1 if not working.
   let opengl = OpenGL::V3_2;
   
@@ -42,60 +98,219 @@ fn main() {
       .exit_on_esc(true)
       .build()
       .unwrap();
-  let mut state = initial_state();
-  let resources = load_resources();
+  let mut state = match load_setting() {
+    Some(path) => match load_state(&path) {
+      Ok(state) => state,
+      Err(err)  => {
+        eprintln!("{}", err);
+        process::exit(1);
+      },
+    },
+    None       => initial_state(),
+  };
+  state.effects = effects_setting();
+  if coop_setting() {
+    set_player_count(&mut state, 2);
+  }
+  let mut resources = match load_resources() {
+    Ok(resources) => resources,
+    Err(err)      => {
+      eprintln!("{}", err);
+      process::exit(1);
+    },
+  };
+  let mut scripts = load_scripts();
   let mut gl = GlGraphics::new(opengl);
   
+  let capture_settings = match capture_setting() {
+    Ok(capture_settings) => capture_settings,
+    Err(err)             => {
+      eprintln!("{}", err);
+      process::exit(1);
+    },
+  };
+  if let Some(capture_settings) = capture_settings {
+    match run_capture(&capture_settings, &mut window, &resources, &scripts, &mut gl) {
+      Ok(frame_count) => println!("captured {} frames to {}", frame_count, capture_settings.output_path),
+      Err(err)        => {
+        eprintln!("{}", err);
+        process::exit(1);
+      },
+    }
+    return;
+  }
+  
+  let heatmap_settings = match heatmap_setting() {
+    Ok(heatmap_settings) => heatmap_settings,
+    Err(err)             => {
+      eprintln!("{}", err);
+      process::exit(1);
+    },
+  };
+  if let Some(heatmap_settings) = heatmap_settings {
+    match run_heatmap(&heatmap_settings, &mut window, &resources, &mut gl) {
+      Ok(())   => println!("saved the heatmap of level {} to {}", heatmap_settings.level_number, heatmap_settings.output_path),
+      Err(err) => {
+        eprintln!("{}", err);
+        process::exit(1);
+      },
+    }
+    return;
+  }
+  
+  let mut netplay = match netplay_setting() {
+    Some(role) => match start_netplay(&role) {
+      Ok(netplay) => {
+        set_player_count(&mut state, 2);
+        Some(netplay)
+      },
+      Err(err)    => {
+        eprintln!("{}", err);
+        process::exit(1);
+      },
+    },
+    None       => None,
+  };
+  let mut netplay_error = None; // once set, the game stops but the error stays on screen until the player quits
+  
+  let mut dev_mode = if dev_setting() { Some(start_dev_mode(&mut state, &mut scripts)) } else { None };
+  let mut audio = load_audio();
+  let record_path = record_setting();
+  let dump_path = dump_setting();
+  let mut time_control = new_time_control();
+  let mut recording = new_replay(&state);
+  let telemetry_path = telemetry_setting();
+  let mut telemetry = new_telemetry_recorder(&state);
+  
   let mut events = window.events();
   while let Some(e) = events.next(&mut window) {
     use piston::input::Button::{ Keyboard };
     use piston::input::Event::{ Render, Input, Update };
     use piston::input::Input::{ Press, Release };
-    use piston::input::keyboard::Key::{ Up, Left, Down, Right,  W, A, S, D,  H, J, K, L,  P, Space };
+    use piston::input::keyboard::Key::{ Up, Left, Down, Right,  W, A, S, D,  H, J, K, L,  M, O, P, Space,  F3, F4, F5, F6, F7 };
     use types::RawInputEvent::*;
     
-    match e {
-      Render(args)                    => render(&mut state, &args, &resources, &mut gl),
-      Update(args)                    => update(&mut state, TimePasses(args.dt)),
+    // the last player, that is the second one in co-op mode, looked up for each event from the current state
+    let wasd_player = state.players.len() - 1;
+    let raw_input_events = match e {
+      Render(args)                    => {
+        render(&mut state, &args, &resources, &dev_errors(&dev_mode), &netplay_error, &time_control, &mut gl);
+        continue;
+      },
+      Update(args)                    => {
+        if let Some(ref mut dev_mode) = dev_mode {
+          reload_changed_files(dev_mode, args.dt, &mut resources, &mut scripts, &mut state);
+        }
+        match scaled_dt(&time_control, args.dt) {
+          Some(dt) => vec![TimePasses(dt)],
+          None     => continue,
+        }
+      },
       
       // arrow keys
-      Input(Press(Keyboard(Up)))      => update(&mut state, PressUp),
-      Input(Press(Keyboard(Left)))    => update(&mut state, PressLeft),
-      Input(Press(Keyboard(Down)))    => update(&mut state, PressDown),
-      Input(Press(Keyboard(Right)))   => update(&mut state, PressRight),
-      Input(Release(Keyboard(Up)))    => update(&mut state, ReleaseUp),
-      Input(Release(Keyboard(Left)))  => update(&mut state, ReleaseLeft),
-      Input(Release(Keyboard(Down)))  => update(&mut state, ReleaseDown),
-      Input(Release(Keyboard(Right))) => update(&mut state, ReleaseRight),
+      Input(Press(Keyboard(Up)))      => vec![PressUp(0)],
+      Input(Press(Keyboard(Left)))    => vec![PressLeft(0)],
+      Input(Press(Keyboard(Down)))    => vec![PressDown(0)],
+      Input(Press(Keyboard(Right)))   => vec![PressRight(0)],
+      Input(Release(Keyboard(Up)))    => vec![ReleaseUp(0)],
+      Input(Release(Keyboard(Left)))  => vec![ReleaseLeft(0)],
+      Input(Release(Keyboard(Down)))  => vec![ReleaseDown(0)],
+      Input(Release(Keyboard(Right))) => vec![ReleaseRight(0)],
       
-      // WASD controls
-      Input(Press(Keyboard(W)))       => update(&mut state, PressUp),
-      Input(Press(Keyboard(A)))       => update(&mut state, PressLeft),
-      Input(Press(Keyboard(S)))       => update(&mut state, PressDown),
-      Input(Press(Keyboard(D)))       => update(&mut state, PressRight),
-      Input(Release(Keyboard(W)))     => update(&mut state, ReleaseUp),
-      Input(Release(Keyboard(A)))     => update(&mut state, ReleaseLeft),
-      Input(Release(Keyboard(S)))     => update(&mut state, ReleaseDown),
-      Input(Release(Keyboard(D)))     => update(&mut state, ReleaseRight),
+      // WASD controls, for the second player in co-op mode
+      Input(Press(Keyboard(W)))       => vec![PressUp(wasd_player)],
+      Input(Press(Keyboard(A)))       => vec![PressLeft(wasd_player)],
+      Input(Press(Keyboard(S)))       => vec![PressDown(wasd_player)],
+      Input(Press(Keyboard(D)))       => vec![PressRight(wasd_player)],
+      Input(Release(Keyboard(W)))     => vec![ReleaseUp(wasd_player)],
+      Input(Release(Keyboard(A)))     => vec![ReleaseLeft(wasd_player)],
+      Input(Release(Keyboard(S)))     => vec![ReleaseDown(wasd_player)],
+      Input(Release(Keyboard(D)))     => vec![ReleaseRight(wasd_player)],
       
       // vim controls
-      Input(Press(Keyboard(K)))       => update(&mut state, PressUp),
-      Input(Press(Keyboard(H)))       => update(&mut state, PressLeft),
-      Input(Press(Keyboard(J)))       => update(&mut state, PressDown),
-      Input(Press(Keyboard(L)))       => update(&mut state, PressRight),
-      Input(Release(Keyboard(K)))     => update(&mut state, ReleaseUp),
-      Input(Release(Keyboard(H)))     => update(&mut state, ReleaseLeft),
-      Input(Release(Keyboard(J)))     => update(&mut state, ReleaseDown),
-      Input(Release(Keyboard(L)))     => update(&mut state, ReleaseRight),
+      Input(Press(Keyboard(K)))       => vec![PressUp(0)],
+      Input(Press(Keyboard(H)))       => vec![PressLeft(0)],
+      Input(Press(Keyboard(J)))       => vec![PressDown(0)],
+      Input(Press(Keyboard(L)))       => vec![PressRight(0)],
+      Input(Release(Keyboard(K)))     => vec![ReleaseUp(0)],
+      Input(Release(Keyboard(H)))     => vec![ReleaseLeft(0)],
+      Input(Release(Keyboard(J)))     => vec![ReleaseDown(0)],
+      Input(Release(Keyboard(L)))     => vec![ReleaseRight(0)],
       
       // pause
-      Input(Release(Keyboard(P)))     => update(&mut state, PressPause),
-      Input(Release(Keyboard(Space))) => update(&mut state, PressPause),
+      Input(Release(Keyboard(P)))     => vec![PressPause],
+      Input(Release(Keyboard(Space))) => vec![PressPause],
+      
+      // show the previous and next levels
+      Input(Release(Keyboard(M)))     => vec![PressMemory],
+      
+      // make the game easier
+      Input(Release(Keyboard(O)))     => vec![PressAssists],
+      
+      // debugging, but the time controls would make the other player wait for us over the network
+      Input(Release(Keyboard(F5))) | Input(Release(Keyboard(F6))) | Input(Release(Keyboard(F7))) if netplay.is_some() => continue,
+      Input(Release(Keyboard(F3)))    => vec![PressInspector],
+      Input(Release(Keyboard(F4)))    => {
+        match save_state(&state, &dump_path) {
+          Ok(())   => println!("dumped the state to {}", dump_path),
+          Err(err) => eprintln!("{}", err),
+        }
+        continue;
+      },
+      Input(Release(Keyboard(F5)))    => {
+        cycle_slow_motion(&mut time_control);
+        continue;
+      },
+      Input(Release(Keyboard(F6)))    => {
+        toggle_freeze(&mut time_control);
+        continue;
+      },
+      Input(Release(Keyboard(F7)))    => step_events(&mut time_control, &state),
       
       // unpause
-      Input(Release(Keyboard(_)))     => update(&mut state, PressAnyKey),
+      Input(Release(Keyboard(_)))     => vec![PressAnyKey],
       
-      _                               => ()
+      _                               => continue,
+    };
+    
+    if netplay_error.is_some() {
+      continue;
+    }
+    for raw_input_event in raw_input_events {
+      let applied_events = match netplay {
+        Some(ref mut netplay) => match netplay_update(netplay, &mut state, &scripts, &mut *audio, raw_input_event) {
+          Ok(applied_events) => applied_events,
+          Err(err)           => {
+            eprintln!("{}", err);
+            netplay_error = Some(err);
+            break;
+          },
+        },
+        None                  => {
+          update(&mut state, &scripts, &mut *audio, raw_input_event);
+          vec![raw_input_event]
+        },
+      };
+      if record_path.is_some() {
+        recording.events.extend(applied_events);
+      }
+      if telemetry_path.is_some() {
+        record_telemetry(&mut telemetry, &state);
+      }
     }
   }
+  
+  if let Some(path) = record_path {
+    if let Err(err) = save_replay(&recording, &path) {
+      eprintln!("{}", err);
+    }
+  }
+  if let Some(path) = telemetry_path {
+    if let Err(err) = save_telemetry(&telemetry, &path) {
+      eprintln!("{}", err);
+    }
+  }
+  if netplay_error.is_some() {
+    process::exit(1);
+  }
 }
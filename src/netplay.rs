@@ -0,0 +1,305 @@
+use std::io::{ self, BufRead, BufReader, Write };
+use std::mem;
+use std::net::{ TcpListener, TcpStream };
+use std::time::Duration;
+
+use audio::*;
+use game::*;
+use replay::*;
+use script::*;
+use serialization::*;
+use settings::*;
+use state::*;
+use types::*;
+use types::RawInputEvent::*;
+
+
+// Two players on two machines, in co-op mode: one runs the game with "--host PORT" and waits for the other, who
+// runs it with "--join ADDRESS:PORT". The host is the first player and the other one the second.
+pub enum NetplayRole {
+  HostR(String), // checked by start_netplay
+  JoinR(String),
+}
+
+// Lock-step: the game only advances by fixed ticks, and before each tick both games send each other the keys
+// pressed since the previous tick and wait for the other's, so both apply the same events in the same order.
+// Each tick also carries a checksum of the state before it, so a difference between the two games is noticed
+// right away instead of growing until the players see different rooms.
+pub struct Netplay {
+  reader: BufReader<TcpStream>,
+  writer: TcpStream,
+  pub local_player: PlayerIndex,
+  tick: u64,
+  local_events: Vec<RawInputEvent>,
+  pending_time: Seconds,
+}
+
+pub const TICK_DURATION: Seconds = 1.0 / 60.0;
+const NETPLAY_PROTOCOL_VERSION: u32 = 1;
+const NETPLAY_TIMEOUT: u64 = 30; // in seconds, so a frozen game doesn't freeze the other one forever
+
+
+pub fn netplay_setting() -> Option<NetplayRole> {
+  use self::NetplayRole::*;
+  
+  match (command_line_option("host"), command_line_option("join")) {
+    (Some(port), _)       => Some(HostR(port)),
+    (None, Some(address)) => Some(JoinR(address)),
+    (None, None)          => None,
+  }
+}
+
+pub fn start_netplay(role: &NetplayRole) -> Result<Netplay, String> {
+  use self::NetplayRole::*;
+  
+  match *role {
+    HostR(ref port)    => {
+      let port: u16 = port.parse().map_err(|_| format!("--host expects a port number, not {:?}", port))?;
+      let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|err| format!("could not listen on port {}: {}", port, err))?;
+      println!("waiting for the other player on port {}", port);
+      host_netplay(listener)
+    },
+    JoinR(ref address) => join_netplay(address),
+  }
+}
+
+pub fn host_netplay(listener: TcpListener) -> Result<Netplay, String> {
+  let (stream, _) = listener.accept().map_err(|err| format!("could not accept the other player: {}", err))?;
+  new_netplay(stream, 0)
+}
+
+pub fn join_netplay(address: &str) -> Result<Netplay, String> {
+  let stream = TcpStream::connect(address).map_err(|err| format!("could not connect to {}: {}", address, err))?;
+  new_netplay(stream, 1)
+}
+
+fn connection_error(err: io::Error) -> String {
+  match err.kind() {
+    io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe =>
+      "the other player has left".to_string(),
+    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut =>
+      format!("the other player has not answered for {} seconds", NETPLAY_TIMEOUT),
+    _ => format!("lost the connection to the other player: {}", err),
+  }
+}
+
+// Both games must be the same version, otherwise they would soon disagree.
+fn greeting() -> String {
+  format!("isrt-netplay {} {}\n", NETPLAY_PROTOCOL_VERSION, STATE_FORMAT_VERSION)
+}
+
+fn new_netplay(stream: TcpStream, local_player: PlayerIndex) -> Result<Netplay, String> {
+  stream.set_nodelay(true).map_err(connection_error)?;
+  stream.set_read_timeout(Some(Duration::from_secs(NETPLAY_TIMEOUT))).map_err(connection_error)?;
+  
+  let mut netplay = Netplay {
+    reader: BufReader::new(stream.try_clone().map_err(connection_error)?),
+    writer: stream,
+    local_player: local_player,
+    tick: 0,
+    local_events: Vec::new(),
+    pending_time: 0.0,
+  };
+  
+  netplay.writer.write_all(greeting().as_bytes()).map_err(connection_error)?;
+  let line = read_line(&mut netplay)?;
+  if line != greeting() {
+    return Err(format!("the other player runs an incompatible version of the game: {:?}", line.trim()));
+  }
+  
+  Ok(netplay)
+}
+
+fn read_line(netplay: &mut Netplay) -> Result<String, String> {
+  let mut line = String::new();
+  match netplay.reader.read_line(&mut line) {
+    Ok(0)    => Err("the other player has left".to_string()),
+    Ok(_)    => Ok(line),
+    Err(err) => Err(connection_error(err)),
+  }
+}
+
+
+// The keys are the same on both machines, e.g. the arrow keys, but each game moves its own player.
+fn player_event(event: RawInputEvent, player_index: PlayerIndex) -> RawInputEvent {
+  match event {
+    PressUp(_)      => PressUp(player_index),
+    PressLeft(_)    => PressLeft(player_index),
+    PressDown(_)    => PressDown(player_index),
+    PressRight(_)   => PressRight(player_index),
+    ReleaseUp(_)    => ReleaseUp(player_index),
+    ReleaseLeft(_)  => ReleaseLeft(player_index),
+    ReleaseDown(_)  => ReleaseDown(player_index),
+    ReleaseRight(_) => ReleaseRight(player_index),
+    event           => event,
+  }
+}
+
+// FNV-1a, which is enough to notice a difference, and is the same on every machine unlike the standard hasher. The
+// particles and the display settings aren't part of the checksum, see gameplay_to_binary.
+pub fn state_checksum(state: &State) -> u64 {
+  gameplay_to_binary(state).iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// A tick looks like this, with the checksum of the state before the tick and the events in order:
+//
+//   tick 42 8c3f0a92e1d54b07 press-right release-right
+fn format_tick(tick: u64, checksum: u64, events: &[RawInputEvent]) -> String {
+  let mut line = format!("tick {} {:016x}", tick, checksum);
+  for &event in events {
+    line.push(' ');
+    line.push_str(event_name(event));
+  }
+  line.push('\n');
+  
+  line
+}
+
+fn parse_tick(line: &str) -> Result<(u64, u64, Vec<RawInputEvent>), String> {
+  let error = || format!("syntax error in netplay: expected a tick, not {:?}", line.trim());
+  
+  let mut words = line.split_whitespace();
+  if words.next() != Some("tick") {
+    return Err(error());
+  }
+  let tick = words.next().and_then(|word| word.parse().ok()).ok_or_else(&error)?;
+  let checksum = words.next().and_then(|word| u64::from_str_radix(word, 16).ok()).ok_or_else(&error)?;
+  let events = words.map(|word| named_event(word).ok_or_else(|| format!("syntax error in netplay: unknown event {:?}", word)))
+                    .collect::<Result<Vec<RawInputEvent>, String>>()?;
+  
+  Ok((tick, checksum, events))
+}
+
+// Sends the local events of the tick, then waits for the other player's.
+fn exchange_tick(netplay: &mut Netplay, state: &State) -> Result<Vec<RawInputEvent>, String> {
+  let checksum = state_checksum(state);
+  let local_events = mem::replace(&mut netplay.local_events, Vec::new());
+  netplay.writer.write_all(format_tick(netplay.tick, checksum, &local_events).as_bytes()).map_err(connection_error)?;
+  
+  let line = read_line(netplay)?;
+  let (tick, other_checksum, other_events) = parse_tick(&line)?;
+  if tick != netplay.tick {
+    return Err(format!("the other player sent tick {} instead of tick {}", tick, netplay.tick));
+  }
+  if other_checksum != checksum {
+    return Err(format!(
+      "desync at tick {}: the two games have diverged (state checksum {:016x} here, {:016x} for the other player)",
+      tick, checksum, other_checksum
+    ));
+  }
+  
+  let other_player = 1 - netplay.local_player;
+  let other_events = other_events.into_iter().map(|event| player_event(event, other_player)).collect();
+  let (host_events, guest_events) = if netplay.local_player == 0 { (local_events, other_events) } else { (other_events, local_events) };
+  
+  let mut events = host_events;
+  events.extend(guest_events);
+  events.push(TimePasses(TICK_DURATION));
+  netplay.tick += 1;
+  
+  Ok(events)
+}
+
+// Instead of update: the display settings are applied right away since they are each player's own, the other
+// events wait for the next tick, and the time which passes is split into ticks. Returns the events which were
+// applied, in order, e.g. to record them.
+pub fn netplay_update(
+  netplay: &mut Netplay,
+  state: &mut State,
+  scripts: &Scripts,
+  audio: &mut dyn Audio,
+  raw_input_event: RawInputEvent
+) -> Result<Vec<RawInputEvent>, String> {
+  let mut applied_events = Vec::new();
+  
+  match raw_input_event {
+    PressMemory | PressInspector => {
+      update(state, scripts, audio, raw_input_event);
+      applied_events.push(raw_input_event);
+    },
+    TimePasses(dt)               => netplay.pending_time += dt,
+    _                            => netplay.local_events.push(player_event(raw_input_event, netplay.local_player)),
+  }
+  
+  while netplay.pending_time >= TICK_DURATION {
+    netplay.pending_time -= TICK_DURATION;
+    for event in exchange_tick(netplay, state)? {
+      update(state, scripts, audio, event);
+      applied_events.push(event);
+    }
+  }
+  
+  Ok(applied_events)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+  use types::AnimatedPos::*;
+  
+  
+  // Each game starts in level 1 in co-op mode and presses its keys at the given ticks.
+  fn play(mut netplay: Netplay, presses: Vec<(u64, RawInputEvent)>, tick_count: u64, variable: Option<i64>) -> Result<State, String> {
+    let scripts = load_scripts();
+    let mut audio = NullAudio;
+    let mut state = level_state(1);
+    set_player_count(&mut state, 2);
+    if let Some(x) = variable {
+      state.script_variables.insert("x".to_string(), x);
+    }
+    
+    for tick in 0..tick_count {
+      for &(_, event) in presses.iter().filter(|&&(t, _)| t == tick) {
+        netplay_update(&mut netplay, &mut state, &scripts, &mut audio, event)?;
+      }
+      netplay_update(&mut netplay, &mut state, &scripts, &mut audio, TimePasses(TICK_DURATION))?;
+    }
+    
+    Ok(state)
+  }
+  
+  // The host runs in another thread, and only its state's checksum comes back.
+  fn play_on_loopback(
+    host_presses: Vec<(u64, RawInputEvent)>,
+    guest_presses: Vec<(u64, RawInputEvent)>,
+    host_variable: Option<i64>
+  ) -> (Result<u64, String>, Result<State, String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let host = thread::spawn(move || {
+      play(host_netplay(listener).unwrap(), host_presses, 120, host_variable).map(|state| state_checksum(&state))
+    });
+    
+    let guest_state = play(join_netplay(&address.to_string()).unwrap(), guest_presses, 120, None);
+    (host.join().unwrap(), guest_state)
+  }
+  
+  
+  #[test]
+  fn both_games_see_both_players() {
+    let (host, guest) = play_on_loopback(
+      vec![(30, PressRight(0)), (31, ReleaseRight(0)), (40, PressMemory)],
+      vec![(30, PressRight(0)), (31, ReleaseRight(0)), (60, PressRight(0)), (61, ReleaseRight(0))],
+      None
+    );
+    let state = guest.unwrap();
+    assert_eq!(host.unwrap(), state_checksum(&state));
+    assert!(match state.players[0].pos { Idle([1, 1]) => true, _ => false });
+    assert!(match state.players[1].pos { Idle([2, 1]) => true, _ => false });
+    assert!(!state.memory_overlay);
+  }
+  
+  #[test]
+  fn hosts_need_a_port_number() {
+    assert_eq!(start_netplay(&NetplayRole::HostR("http".to_string())).err().unwrap(), "--host expects a port number, not \"http\"");
+  }
+  
+  #[test]
+  fn diverging_games_are_stopped() {
+    let (host, guest) = play_on_loopback(vec![], vec![], Some(1));
+    assert!(host.unwrap_err().starts_with("desync at tick 0:"));
+    assert!(guest.err().unwrap().starts_with("desync at tick 0:"));
+  }
+}
@@ -0,0 +1,118 @@
+use std::f64::consts::PI;
+use graphics::types::Color;
+
+use settings::*;
+use types::*;
+
+
+// Purely cosmetic: the particles don't interact with anything, and can be hidden with "--calm". They must stay that
+// way, because netplay doesn't check that both games have the same particles, see gameplay_to_binary.
+#[derive(Clone,Serialize,Deserialize)]
+pub struct Particle {
+  pub f_pos: FPos, // at t0, in cells
+  pub f_speed: FSpeed, // at t0, slows down to zero at the end of the particle's life
+  pub t0: Seconds,
+  pub duration: Seconds,
+  pub color: Color,
+}
+
+#[derive(Clone,Copy)]
+pub enum Burst {
+  DeathB,
+  SparksB,
+  DustB,
+}
+
+struct BurstDescription {
+  count: usize,
+  speed: f64, // cells per second
+  duration: Seconds,
+  colors: &'static [Color],
+}
+
+const PLAYER_ORANGE: Color = [1.0, 0.75, 0.0, 1.0];
+const OUTLINE_BLACK: Color = [0.0, 0.0, 0.0, 1.0];
+const SPARK_YELLOW:  Color = [1.0, 1.0, 0.6, 1.0];
+const SPARK_WHITE:   Color = [1.0, 1.0, 1.0, 1.0];
+const DUST_BROWN:    Color = [0.6, 0.5, 0.4, 0.6];
+
+fn burst_description(burst: Burst) -> BurstDescription {
+  use self::Burst::*;
+  
+  match burst {
+    DeathB  => BurstDescription { count: 24, speed: 3.0, duration: 0.6, colors: &[PLAYER_ORANGE, PLAYER_ORANGE, OUTLINE_BLACK] },
+    SparksB => BurstDescription { count: 6,  speed: 4.0, duration: 0.2, colors: &[SPARK_YELLOW, SPARK_WHITE] },
+    DustB   => BurstDescription { count: 10, speed: 1.0, duration: 0.5, colors: &[DUST_BROWN] },
+  }
+}
+
+// The particles are spread using the golden angle instead of random numbers, so that replays look exactly the same
+// every time. `center` is in cells, e.g. [2.5, 3.5] is the center of the cell [2, 3].
+pub fn emit_burst(particles: &mut Vec<Particle>, burst: Burst, center: FPos, t: Seconds) {
+  let golden_angle = PI * (3.0 - (5.0 as f64).sqrt());
+  let description = burst_description(burst);
+  
+  // rotate each burst a bit differently
+  let initial_angle = (t * 1000.0) % (2.0 * PI);
+  for i in 0..description.count {
+    let angle = initial_angle + i as f64 * golden_angle;
+    let speed = description.speed * (0.5 + 0.5 * ((i * 7) % 11) as f64 / 10.0);
+    
+    particles.push(
+      Particle {
+        f_pos: center,
+        f_speed: [speed * angle.cos(), speed * angle.sin()],
+        t0: t,
+        duration: description.duration,
+        color: description.colors[i % description.colors.len()],
+      }
+    );
+  }
+}
+
+pub fn compute_particle_f_pos(particle: &Particle, t: Seconds) -> FPos {
+  let dt = (t - particle.t0).max(0.0).min(particle.duration);
+  
+  // the speed decreases linearly, so the distance is the area of a trapezoid
+  let distance_factor = dt - dt * dt / (2.0 * particle.duration);
+  [particle.f_pos[0] + particle.f_speed[0] * distance_factor,
+   particle.f_pos[1] + particle.f_speed[1] * distance_factor]
+}
+
+// fades out towards the end
+pub fn compute_particle_color(particle: &Particle, t: Seconds) -> Color {
+  let remaining = 1.0 - (t - particle.t0) / particle.duration;
+  let mut color = particle.color;
+  color[3] *= remaining.max(0.0).min(1.0) as f32;
+  
+  color
+}
+
+// "--calm" disables the particles and the screen shake.
+pub fn effects_setting() -> bool {
+  !command_line_flag("calm")
+}
+
+pub fn update_particles(particles: &mut Vec<Particle>, t: Seconds) {
+  particles.retain(|particle| t < particle.t0 + particle.duration);
+}
+
+
+// The screen shakes briefly when the player dies.
+pub const SHAKE_DURATION: Seconds = 0.3;
+const SHAKE_AMPLITUDE: f64 = 2.0; // in sprite pixels
+const SHAKE_FREQUENCY: f64 = 25.0; // back-and-forths per second
+
+// In sprite pixels, to be added to the position of everything in the room.
+pub fn compute_shake_offset(shake_t0: Option<Seconds>, t: Seconds) -> FPos {
+  match shake_t0 {
+    Some(t0) if t >= t0 && t < t0 + SHAKE_DURATION => {
+      let dt = t - t0;
+      let amplitude = SHAKE_AMPLITUDE * (1.0 - dt / SHAKE_DURATION);
+      let phase = 2.0 * PI * SHAKE_FREQUENCY * dt;
+      
+      [(amplitude * phase.sin()).round(), (amplitude * (1.3 * phase).cos()).round()]
+    },
+    _ => [0.0, 0.0],
+  }
+}
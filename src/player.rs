@@ -1,10 +1,12 @@
 use graphics::math::*;
 
 use levels::*;
+use settings::*;
 use types::*;
 use types::AnimatedPos::*;
 
 
+#[derive(Clone,Serialize,Deserialize)]
 pub struct Player {
   pub up_pressed:    bool,
   pub left_pressed:  bool,
@@ -15,6 +17,24 @@ pub struct Player {
   pub pos: AnimatedPos,
 }
 
+// "--coop" adds a second player, who uses WASD while the first player uses the arrow keys.
+pub fn coop_setting() -> bool {
+  command_line_flag("coop")
+}
+
+// A player who is walking into level_dst through a door, and who will stand in front of it at t_dst.
+pub fn entering_player(level_src: LevelNumber, level_dst: LevelNumber, t_dst: Seconds) -> Player {
+  Player {
+    up_pressed:    false,
+    left_pressed:  false,
+    down_pressed:  false,
+    right_pressed: false,
+    most_recent_dir: None,
+    buffered_dir:    None,
+    pos: moving_in(level_src, level_dst, t_dst),
+  }
+}
+
 
 // floating point version of player.pos which takes movement into account, so the
 // position can be in-between two cells.
@@ -40,38 +60,39 @@ pub fn compute_player_f_pos(player: &AnimatedPos, t: Seconds) -> FPos {
   
   linear_motion(pos, f_speed, t0, t)
 }
-  
 
 
-fn try_move_action(level_number: LevelNumber, pos: Pos, dir: Dir) -> Option<Action> {
+
+fn try_move_action(player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, pos: Pos, dir: Dir) -> Option<Action> {
   use levels::Cell::*;
   use types::Action::*;
   
   match cell_at(level_number, pos) {
     LeftDoorC  => {
       if dir == LEFT {
-        return Some(PreviousLevel);
+        return Some(PreviousLevel(player_index));
       }
     },
     RightDoorC => {
       if dir == RIGHT {
-        return Some(NextLevel);
+        return Some(NextLevel(player_index));
       }
     },
     _          => {},
   }
   
-  match cell_at(level_number, add(pos, dir)) {
+  match current_cell_at(level_number, temporary_walls, add(pos, dir)) {
     SignC(message) => Some(ReadSign(message)),
-    WallC          => None,
-    _              => Some(Move(pos, dir)),
+    SpawnerC(_)    => Some(Bump),
+    WallC          => Some(Bump),
+    _              => Some(Move(player_index, pos, dir)),
   }
 }
 
 
-pub fn initiate_move(player: &mut Player, level_number: LevelNumber, dir: Dir) -> Option<Action> {
+pub fn initiate_move(player: &mut Player, player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, dir: Dir) -> Option<Action> {
   match player.pos {
-    Idle(pos)       => try_move_action(level_number, pos, dir),
+    Idle(pos)       => try_move_action(player_index, level_number, temporary_walls, pos, dir),
     _               => None,
   }
 }
@@ -91,35 +112,79 @@ pub fn release_direction(is_pressed: &mut bool) {
 }
 
 
-fn continue_moving(player: &mut Player, level_number: LevelNumber) -> Option<Action> {
+// Like try_move_action, but for movements the player didn't ask for: bumping into a sign while sliding
+// shouldn't read it, and sliding into a wall shouldn't make a sound on every frame.
+fn slide_action(player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, pos: Pos, dir: Dir) -> Option<Action> {
+  use types::Action::*;
+  
+  match try_move_action(player_index, level_number, temporary_walls, pos, dir) {
+    Some(Move(player_index, pos, dir)) => Some(Slide(player_index, pos, dir)),
+    _                                  => None,
+  }
+}
+
+// On ice, the player keeps sliding in the direction in which they entered the cell.
+fn ice_action(player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, pos: Pos, dir: Dir) -> Option<Action> {
+  use levels::Cell::*;
+  
+  match cell_at(level_number, pos) {
+    IceC => slide_action(player_index, level_number, temporary_walls, pos, dir),
+    _    => None,
+  }
+}
+
+// On a conveyor, the player is pushed in the conveyor's direction unless they walk away.
+fn conveyor_action(player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, pos: Pos) -> Option<Action> {
+  use levels::Cell::*;
+  
+  match cell_at(level_number, pos) {
+    ConveyorC(dir) => slide_action(player_index, level_number, temporary_walls, pos, dir),
+    _              => None,
+  }
+}
+
+fn continue_walking(player: &mut Player, player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>) -> Option<Action> {
   // If the user holds right and taps down, we want to go down one cell and then continue going right.
-  if player.buffered_dir == Some(UP)    { return initiate_move(player, level_number, UP);    }
-  if player.buffered_dir == Some(LEFT)  { return initiate_move(player, level_number, LEFT);  }
-  if player.buffered_dir == Some(DOWN)  { return initiate_move(player, level_number, DOWN);  }
-  if player.buffered_dir == Some(RIGHT) { return initiate_move(player, level_number, RIGHT); }
+  if player.buffered_dir == Some(UP)    { return initiate_move(player, player_index, level_number, temporary_walls, UP);    }
+  if player.buffered_dir == Some(LEFT)  { return initiate_move(player, player_index, level_number, temporary_walls, LEFT);  }
+  if player.buffered_dir == Some(DOWN)  { return initiate_move(player, player_index, level_number, temporary_walls, DOWN);  }
+  if player.buffered_dir == Some(RIGHT) { return initiate_move(player, player_index, level_number, temporary_walls, RIGHT); }
   
   // If the user is holding several keys, favour the most recent one.
-  if player.up_pressed    && player.most_recent_dir == Some(UP)    { return initiate_move(player, level_number, UP);    }
-  if player.left_pressed  && player.most_recent_dir == Some(LEFT)  { return initiate_move(player, level_number, LEFT);  }
-  if player.down_pressed  && player.most_recent_dir == Some(DOWN)  { return initiate_move(player, level_number, DOWN);  }
-  if player.right_pressed && player.most_recent_dir == Some(RIGHT) { return initiate_move(player, level_number, RIGHT); }
+  if player.up_pressed    && player.most_recent_dir == Some(UP)    { return initiate_move(player, player_index, level_number, temporary_walls, UP);    }
+  if player.left_pressed  && player.most_recent_dir == Some(LEFT)  { return initiate_move(player, player_index, level_number, temporary_walls, LEFT);  }
+  if player.down_pressed  && player.most_recent_dir == Some(DOWN)  { return initiate_move(player, player_index, level_number, temporary_walls, DOWN);  }
+  if player.right_pressed && player.most_recent_dir == Some(RIGHT) { return initiate_move(player, player_index, level_number, temporary_walls, RIGHT); }
   
   // Continue moving in one of the pressed directions even if none is the most recent.
-  if player.up_pressed    { return initiate_move(player, level_number, UP);    }
-  if player.left_pressed  { return initiate_move(player, level_number, LEFT);  }
-  if player.down_pressed  { return initiate_move(player, level_number, DOWN);  }
-  if player.right_pressed { return initiate_move(player, level_number, RIGHT); }
+  if player.up_pressed    { return initiate_move(player, player_index, level_number, temporary_walls, UP);    }
+  if player.left_pressed  { return initiate_move(player, player_index, level_number, temporary_walls, LEFT);  }
+  if player.down_pressed  { return initiate_move(player, player_index, level_number, temporary_walls, DOWN);  }
+  if player.right_pressed { return initiate_move(player, player_index, level_number, temporary_walls, RIGHT); }
   
   None
 }
 
-pub fn update_player(player: &mut Player, level_number: LevelNumber, t: Seconds) -> Option<Action> {
+fn continue_moving(player: &mut Player, player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, pos: Pos, dir: Dir) -> Option<Action> {
+  // The player has no control while sliding on ice, so keys pressed in the meantime are ignored, except for taps,
+  // which remain buffered until the slide is over.
+  ice_action(player_index, level_number, temporary_walls, pos, dir)
+    .or_else(|| continue_walking(player, player_index, level_number, temporary_walls))
+    .or_else(|| conveyor_action(player_index, level_number, temporary_walls, pos))
+}
+
+pub fn update_player(player: &mut Player, player_index: PlayerIndex, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, t: Seconds) -> Option<Action> {
   match player.pos {
+    Idle(pos) => {
+      // e.g. after reading a sign while standing on a conveyor
+      conveyor_action(player_index, level_number, temporary_walls, pos)
+    },
     MovingSince(pos, dir, t0) => {
       if t >= t0 + PLAYER_MOVE_DURATION {
-        player.pos = Idle(add(pos, dir));
+        let dst = add(pos, dir);
+        player.pos = Idle(dst);
         
-        continue_moving(player, level_number)
+        continue_moving(player, player_index, level_number, temporary_walls, dst, dir)
       } else {
         None
       }
@@ -140,11 +205,10 @@ pub fn update_player(player: &mut Player, level_number: LevelNumber, t: Seconds)
             };
         player.pos = Idle(door_dst);
         
-        continue_moving(player, level_number)
+        continue_walking(player, player_index, level_number, temporary_walls)
       } else {
         None
       }
     },
-    _ => None,
   }
 }
@@ -6,22 +6,48 @@ extern crate piston;
 
 use graphics::*;
 use graphics::math::{ Matrix2d };
+use graphics::types::Color;
 use opengl_graphics::{ GlGraphics, Texture };
 
+use animation::*;
 use corpse::*;
 use font::*;
+use i18n::*;
+use inspector::*;
+use layout::*;
 use levels::*;
+use particle::*;
 use player::*;
 use resources::*;
 use spiny::*;
 use state::*;
+use telemetry::*;
+use time_control::*;
 use types::*;
 
 
 pub const SPRITE_PIXEL_SIZE: u8 = 5;
 pub const LIFETIME_PIXEL_SIZE: u8 = 2;
 
-fn draw_transparent_sprite(texture: &Texture, f_pos: FPos, alpha: f64, transform: Matrix2d, gl: &mut GlGraphics) {
+const INSPECTOR_GRID_COLOR: Color = [1.0, 0.0, 1.0, 0.5];
+const PLAYER_TINTS:         &'static [Color] = &[[1.0, 1.0, 1.0, 1.0], [0.6, 0.8, 1.0, 1.0]]; // one per player
+const HEAT_COLOR:           Color = [1.0, 0.2, 0.0, 0.75]; // the cell in which the players spent the most time
+const DEATH_COLOR:          Color = [0.0, 0.0, 0.0, 1.0];
+
+fn multiply_colors(color1: Color, color2: Color) -> Color {
+  [color1[0] * color2[0], color1[1] * color2[1], color1[2] * color2[2], color1[3] * color2[3]]
+}
+
+// Draws one of the sprites of a texture containing a grid of sprites, e.g. a sprite sheet.
+fn draw_tinted_sprite_from_grid(
+  texture: &Texture,
+  grid_width: SpriteIndex,
+  sprite_index: SpriteIndex,
+  f_pos: FPos,
+  color: Color,
+  transform: Matrix2d,
+  gl: &mut GlGraphics
+) {
   unsafe {
     // Sharp pixels please!
     gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
@@ -30,12 +56,15 @@ fn draw_transparent_sprite(texture: &Texture, f_pos: FPos, alpha: f64, transform
     gl::Disable(gl::FRAMEBUFFER_SRGB);
   }
   
-  let color = [1.0, 1.0, 1.0, alpha as f32];
   let dx = f_pos[0] * SPRITE_WIDTH as f64;
   let dy = f_pos[1] * SPRITE_HEIGHT as f64;
   let xform = transform.scale(SPRITE_PIXEL_SIZE as f64, SPRITE_PIXEL_SIZE as f64)
                        .trans(dx, dy);
-  Image::new_color(color).draw(texture, &Default::default(), xform, gl);
+  let x_src = (sprite_index % grid_width) as f64 * SPRITE_WIDTH  as f64;
+  let y_src = (sprite_index / grid_width) as f64 * SPRITE_HEIGHT as f64;
+  Image::new_color(color).src_rect([x_src, y_src, SPRITE_WIDTH as f64, SPRITE_HEIGHT as f64])
+                         .rect([0.0, 0.0, SPRITE_WIDTH as f64, SPRITE_HEIGHT as f64])
+                         .draw(texture, &Default::default(), xform, gl);
   
   unsafe {
     // Sometimes the pixels still aren't sharp. There is no logical reason why setting this again after the
@@ -44,26 +73,72 @@ fn draw_transparent_sprite(texture: &Texture, f_pos: FPos, alpha: f64, transform
   }
 }
 
+fn draw_tinted_sprite(texture: &Texture, f_pos: FPos, color: Color, transform: Matrix2d, gl: &mut GlGraphics) {
+  draw_tinted_sprite_from_grid(texture, 1, 0, f_pos, color, transform, gl);
+}
+
 fn draw_sprite(texture: &Texture, f_pos: FPos, transform: Matrix2d, gl: &mut GlGraphics) {
-  draw_transparent_sprite(texture, f_pos, 1.0, transform, gl);
+  draw_tinted_sprite(texture, f_pos, [1.0, 1.0, 1.0, 1.0], transform, gl);
 }
 
-fn draw_time_bound_sprite(
-  texture: &Texture,
+// `dt` is the time since the start of the animation.
+fn draw_animated_sprite(
+  sprite_sheet: &SpriteSheet,
+  animation: &str,
+  dt: Seconds,
   f_pos: FPos,
-  lifetime: &Lifetime,
-  resources: &Resources,
+  color: Color,
   transform: Matrix2d,
   gl: &mut GlGraphics
 ) {
-  draw_sprite(texture, f_pos, transform, gl);
+  let sprite_index = animation_sprite_index(sprite_sheet, animation, dt);
+  draw_tinted_sprite_from_grid(&sprite_sheet.texture, sprite_sheet.grid_width, sprite_index, f_pos, color, transform, gl);
+}
+
+// The sprite should be facing right, we rotate it to face the given direction.
+fn draw_oriented_sprite(texture: &Texture, f_pos: FPos, dir: Dir, transform: Matrix2d, gl: &mut GlGraphics) {
+  let angle: Radians = (dir[1] as f64).atan2(dir[0] as f64);
+  let half_width  = SPRITE_WIDTH  as f64 * SPRITE_PIXEL_SIZE as f64 / 2.0;
+  let half_height = SPRITE_HEIGHT as f64 * SPRITE_PIXEL_SIZE as f64 / 2.0;
+  let dx = (f_pos[0] + 0.5) * SPRITE_WIDTH  as f64 * SPRITE_PIXEL_SIZE as f64;
+  let dy = (f_pos[1] + 0.5) * SPRITE_HEIGHT as f64 * SPRITE_PIXEL_SIZE as f64;
   
+  // rotate around the center of the sprite
+  let xform = transform.trans(dx, dy)
+                       .rot_rad(angle)
+                       .trans(-half_width, -half_height);
+  draw_sprite(texture, [0.0, 0.0], xform, gl);
+}
+
+fn draw_lifetime_label(f_pos: FPos, lifetime: &Lifetime, color: Color, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
   let lifetime_text = format!("{}-{}", lifetime.level_min, lifetime.level_max);
   let dx = (f_pos[0] + 1.0) * SPRITE_WIDTH as f64 * SPRITE_PIXEL_SIZE as f64;
   let dy = (f_pos[1] + 1.0) * SPRITE_HEIGHT as f64 * SPRITE_PIXEL_SIZE as f64;
   let xform = transform.trans(dx, dy)
                        .scale(LIFETIME_PIXEL_SIZE as f64, LIFETIME_PIXEL_SIZE as f64);
-  draw_text_bottom_right(&lifetime_text, &resources.small_font, xform, gl);
+  
+  // the label is tinted like the sprite, on top of the theme's colors
+  let theme = &resources.theme;
+  let label_color = multiply_colors(theme.lifetime_color, color);
+  if let Some(background) = theme.lifetime_background {
+    let width = resources.small_font.sprite_width * lifetime_text.chars().count() as f64;
+    let height = resources.small_font.sprite_height;
+    rectangle(multiply_colors(background, color), [-width, -height, width, height], xform, gl);
+  }
+  draw_text_bottom_right(&lifetime_text, &resources.small_font, label_color, xform, gl);
+}
+
+fn draw_time_bound_sprite(
+  texture: &Texture,
+  f_pos: FPos,
+  lifetime: &Lifetime,
+  color: Color,
+  resources: &Resources,
+  transform: Matrix2d,
+  gl: &mut GlGraphics
+) {
+  draw_tinted_sprite(texture, f_pos, color, transform, gl);
+  draw_lifetime_label(f_pos, lifetime, color, resources, transform, gl);
 }
 
 
@@ -72,10 +147,14 @@ fn draw_lower_cell(level_number: LevelNumber, pos: Pos, resources: &Resources, t
   
   let f_pos = [pos[0] as f64, pos[1] as f64];
   match cell_at(level_number, pos) {
-    LeftDoorC   => draw_sprite(&resources.start,    f_pos, transform, gl),
-    RightDoorC  => draw_sprite(&resources.goal,     f_pos, transform, gl),
-    SignC(_)    => draw_sprite(&resources.sign,     f_pos, transform, gl),
-    _           => draw_sprite(&resources.floor,    f_pos, transform, gl),
+    IceC              => draw_sprite(&resources.ice,      f_pos, transform, gl),
+    ConveyorC(dir)    => draw_oriented_sprite(&resources.conveyor, f_pos, dir,         transform, gl),
+    LeftDoorC         => draw_sprite(&resources.start,    f_pos, transform, gl),
+    RightDoorC        => draw_sprite(&resources.goal,     f_pos, transform, gl),
+    SignC(_)          => draw_sprite(&resources.sign,     f_pos, transform, gl),
+    SinkC             => draw_sprite(&resources.sink,     f_pos, transform, gl),
+    SpawnerC(spawner) => draw_oriented_sprite(&resources.spawner,  f_pos, spawner.dir, transform, gl),
+    _                 => draw_sprite(&resources.floor,    f_pos, transform, gl),
   }
 }
 
@@ -83,12 +162,26 @@ fn draw_static_entity(level_number: LevelNumber, pos: Pos, resources: &Resources
   use levels::Entity::*;
   
   match entity_at(level_number, pos) {
-    Some(SpinyE(moving_spiny))  => draw_spiny(&moving_spiny, 0.0, 0.0, &resources, transform, gl),
+    Some(SpinyE(moving_spiny))  => draw_spiny(&moving_spiny, SPINY_SPEED, 0.0, 0.0, &resources, transform, gl),
     Some(WallE(temporary_wall)) => draw_temporary_wall(&temporary_wall, &resources, transform, gl),
     _                           => {},
   }
 }
 
+fn draw_remembered_entity(level_number: LevelNumber, pos: Pos, color: Color, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  use levels::Entity::*;
+  
+  let f_pos = [pos[0] as f64, pos[1] as f64];
+  match entity_at(level_number, pos) {
+    Some(SpinyE(moving_spiny))  => {
+      draw_animated_sprite(&resources.spiny, "idle", 0.0, f_pos, color, transform, gl);
+      draw_lifetime_label(f_pos, &moving_spiny.lifetime, color, resources, transform, gl);
+    },
+    Some(WallE(temporary_wall)) => draw_time_bound_sprite(&resources.wall, f_pos, &temporary_wall.lifetime, color, resources, transform, gl),
+    _                           => {},
+  }
+}
+
 fn draw_upper_cell(level_number: LevelNumber, pos: Pos, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
   use levels::Cell::*;
   
@@ -120,6 +213,20 @@ fn draw_static_entities(level_number: LevelNumber, resources: &Resources, transf
   }
 }
 
+fn draw_memory_overlay(level_number: LevelNumber, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let remembered_levels = [(level_number - 1, resources.theme.previous_level_tint),
+                           (level_number + 1, resources.theme.next_level_tint)];
+  for &(remembered_level, color) in &remembered_levels {
+    if remembered_level >= min_level() && remembered_level <= max_level() {
+      for j in 0..LEVEL_HEIGHT {
+        for i in 0..LEVEL_WIDTH {
+          draw_remembered_entity(remembered_level, [i,j], color, resources, transform, gl);
+        }
+      }
+    }
+  }
+}
+
 fn draw_upper_level(level_number: LevelNumber, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
   for j in 0..LEVEL_HEIGHT {
     for i in 0..LEVEL_WIDTH {
@@ -129,25 +236,60 @@ fn draw_upper_level(level_number: LevelNumber, resources: &Resources, transform:
 }
 
 
-fn draw_player(player: &Player, t: Seconds, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
-  draw_sprite(&resources.player, compute_player_f_pos(&player.pos, t), transform, gl);
+fn walk_animation(dir: Dir) -> &'static str {
+  match dir {
+    UP   => "walk-up",
+    LEFT => "walk-left",
+    DOWN => "walk-down",
+    _    => "walk-right",
+  }
+}
+
+// The walk cycle restarts at every cell, and the doors are entered and exited sideways.
+fn player_animation(pos: &AnimatedPos, t: Seconds) -> (&'static str, Seconds) {
+  use types::AnimatedPos::*;
+  
+  match *pos {
+    Idle(_)                                  => ("idle", t),
+    MovingSince(_, dir, t0)                  => (walk_animation(dir), t - t0),
+    MovingOutSince(level_src, level_dst, t0) => {
+      let dir = if level_dst < level_src { LEFT } else { RIGHT };
+      (walk_animation(dir), t - t0)
+    },
+    MovingInUntil(level_src, level_dst, t1)  => {
+      let dir = if level_dst < level_src { LEFT } else { RIGHT };
+      (walk_animation(dir), t - (t1 - PLAYER_MOVE_DURATION))
+    },
+  }
 }
 
-fn draw_corpse(corpse: &Corpse, t: Seconds, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
-  let dt = t - corpse.t0;
-  let alpha = 1.0 - CORPSE_SPEED * dt;
+// In co-op mode, the players are told apart by their tint.
+fn draw_player(player: &Player, player_index: PlayerIndex, t: Seconds, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let (animation, dt) = player_animation(&player.pos, t);
+  let tint = PLAYER_TINTS[player_index % PLAYER_TINTS.len()];
   
-  draw_transparent_sprite(&resources.player, corpse.f_pos, alpha, transform, gl);
+  draw_animated_sprite(&resources.player, animation, dt, compute_player_f_pos(&player.pos, t), tint, transform, gl);
+}
+
+fn draw_corpse(corpse: &Corpse, t: Seconds, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  draw_animated_sprite(&resources.player, "death", t - corpse.t0, corpse.f_pos, [1.0, 1.0, 1.0, 1.0], transform, gl);
 }
 
-fn draw_spiny(spiny: &MovingSpiny, t0: Seconds, t: Seconds, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
-  draw_time_bound_sprite(&resources.spiny, compute_spiny_f_pos(spiny, t0, t), &spiny.lifetime, resources, transform, gl);
+// All the spinies move in unison, so they all roll in sync with the time at which they started moving. Slower
+// spinies roll slower too, so they don't look like they are slipping.
+fn draw_spiny(spiny: &MovingSpiny, speed: f64, t0: Seconds, t: Seconds, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let f_pos = compute_spiny_f_pos(spiny, speed, t0, t);
+  let animation = if spiny.enabled { "roll" } else { "idle" };
+  let color = [1.0, 1.0, 1.0, 1.0];
+  
+  draw_animated_sprite(&resources.spiny, animation, (t - t0) * speed / SPINY_SPEED, f_pos, color, transform, gl);
+  draw_lifetime_label(f_pos, &spiny.lifetime, color, resources, transform, gl);
 }
 
 fn draw_temporary_wall(temporary_wall: &TemporaryWall, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
   let f_pos = [temporary_wall.pos[0] as f64, temporary_wall.pos[1] as f64];
   
-  draw_time_bound_sprite(&resources.wall, f_pos, &temporary_wall.lifetime, resources, transform, gl);
+  draw_time_bound_sprite(&resources.wall, f_pos, &temporary_wall.lifetime, [1.0, 1.0, 1.0, 1.0], resources, transform, gl);
 }
 
 fn draw_entities(state: &State, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
@@ -155,10 +297,13 @@ fn draw_entities(state: &State, resources: &Resources, transform: Matrix2d, gl:
     draw_corpse(corpse, state.time, resources, transform, gl);
   }
   
-  draw_player(&state.player, state.time, resources, transform, gl);
+  // the first player is drawn on top
+  for (player_index, player) in state.players.iter().enumerate().rev() {
+    draw_player(player, player_index, state.time, resources, transform, gl);
+  }
   
   for spiny in &state.spinies {
-    draw_spiny(spiny, state.spinies_moving_since, state.time, resources, transform, gl);
+    draw_spiny(spiny, state.spinies_speed, state.spinies_moving_since, state.time, resources, transform, gl);
   }
   
   for wall in &state.temporary_walls {
@@ -166,12 +311,150 @@ fn draw_entities(state: &State, resources: &Resources, transform: Matrix2d, gl:
   }
 }
 
+// Each particle is a single sprite pixel, aligned to the pixel grid of the sprites.
+fn draw_particles(particles: &Vec<Particle>, t: Seconds, transform: Matrix2d, gl: &mut GlGraphics) {
+  let pixel_size = SPRITE_PIXEL_SIZE as f64;
+  for particle in particles {
+    let f_pos = compute_particle_f_pos(particle, t);
+    let x = (f_pos[0] * SPRITE_WIDTH  as f64).floor() * pixel_size;
+    let y = (f_pos[1] * SPRITE_HEIGHT as f64).floor() * pixel_size;
+    
+    rectangle(compute_particle_color(particle, t), [x, y, pixel_size, pixel_size], transform, gl);
+  }
+}
+
+
+// In debug mode, the cells are outlined, and their coordinates are written on the outer walls.
+fn draw_inspector_grid(resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let cell_width  = SPRITE_WIDTH  as f64 * SPRITE_PIXEL_SIZE as f64;
+  let cell_height = SPRITE_HEIGHT as f64 * SPRITE_PIXEL_SIZE as f64;
+  let room_width  = LEVEL_WIDTH  as f64 * cell_width;
+  let room_height = LEVEL_HEIGHT as f64 * cell_height;
+  
+  for i in 0..LEVEL_WIDTH + 1 {
+    let x = i as f64 * cell_width;
+    line(INSPECTOR_GRID_COLOR, 0.5, [x, 0.0, x, room_height], transform, gl);
+  }
+  for j in 0..LEVEL_HEIGHT + 1 {
+    let y = j as f64 * cell_height;
+    line(INSPECTOR_GRID_COLOR, 0.5, [0.0, y, room_width, y], transform, gl);
+  }
+  
+  for i in 0..LEVEL_WIDTH {
+    draw_text(&i.to_string(), &resources.white_font, transform.trans(i as f64 * cell_width, 0.0), gl);
+  }
+  for j in 0..LEVEL_HEIGHT {
+    draw_text(&j.to_string(), &resources.white_font, transform.trans(0.0, j as f64 * cell_height), gl);
+  }
+}
+
+// The state is listed inside the room, below the top wall, over a backdrop which makes it readable.
+fn draw_inspector_text(state: &State, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let font = &resources.big_font;
+  let lines = inspector_lines(state);
+  let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as f64 * font.sprite_width;
+  let height = lines.len() as f64 * font.sprite_height;
+  let xform = transform.trans(0.0, SPRITE_HEIGHT as f64 * SPRITE_PIXEL_SIZE as f64);
+  
+  rectangle(resources.theme.overlay_color, [0.0, 0.0, width, height], xform, gl);
+  draw_text(&lines.join("\n"), font, xform, gl);
+}
+
+// The errors are displayed on top of everything else.
+fn draw_error_box(message_box: &MessageBox, args: &piston::input::RenderArgs, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let max_columns = (args.width as f64 / resources.big_font.sprite_width) as usize;
+  let lines = layout_message_box(message_box, max_columns);
+  
+  rectangle(resources.theme.overlay_color, [0.0, 0.0, args.width as f64, args.height as f64], transform, gl);
+  draw_text(&lines.join("\n"), &resources.big_font, transform, gl);
+}
+
+// In dev mode, the files which couldn't be reloaded.
+fn draw_dev_errors(dev_errors: &Vec<String>, args: &piston::input::RenderArgs, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let message_box = MessageBox {
+    title: Some("could not reload".to_string()),
+    body: dev_errors.join("\n\n"),
+    choices: Vec::new(),
+    selected_choice: 0,
+    footer: Some("the previous version is still in use".to_string()),
+  };
+  draw_error_box(&message_box, args, resources, transform, gl);
+}
+
+// The game can't go on without the other player, e.g. once they have left.
+fn draw_netplay_error(netplay_error: &str, args: &piston::input::RenderArgs, resources: &Resources, transform: Matrix2d, gl: &mut GlGraphics) {
+  let message_box = MessageBox {
+    title: Some(catalog_text(&resources.catalog, "netplay-stopped")),
+    body: netplay_error.to_string(),
+    choices: Vec::new(),
+    selected_choice: 0,
+    footer: Some(catalog_text(&resources.catalog, "press-esc-to-quit")),
+  };
+  draw_error_box(&message_box, args, resources, transform, gl);
+}
 
-pub fn render(state: &State, args: &piston::input::RenderArgs, resources: &Resources, gl: &mut GlGraphics) {
+// The room as the player first sees it, with the cells tinted by the time which the players spent in them, the
+// number of visits in their corner, and a dot wherever a player died.
+pub fn render_heatmap(
+  level_number: LevelNumber,
+  telemetry: &LevelTelemetry,
+  args: &piston::input::RenderArgs,
+  resources: &Resources,
+  gl: &mut GlGraphics
+) {
+  let cell_width  = SPRITE_WIDTH  as f64 * SPRITE_PIXEL_SIZE as f64;
+  let cell_height = SPRITE_HEIGHT as f64 * SPRITE_PIXEL_SIZE as f64;
+  let max_duration = telemetry.cells.values().map(|cell| cell.duration).fold(0.0, f64::max);
+  
   gl.draw(args.viewport(), |c, gl| {
-    clear([1.0, 1.0, 1.0, 1.0], gl);
+    clear(resources.theme.clear_color, gl);
     
-    let transform = c.transform;
+    draw_lower_level(level_number, resources, c.transform, gl);
+    draw_static_entities(level_number, resources, c.transform, gl);
+    draw_upper_level(level_number, resources, c.transform, gl);
+    
+    for (pos, cell) in &telemetry.cells {
+      let xform = c.transform.trans(pos[0] as f64 * cell_width, pos[1] as f64 * cell_height);
+      if max_duration > 0.0 {
+        let heat = cell.duration / max_duration;
+        let color = [HEAT_COLOR[0], HEAT_COLOR[1], HEAT_COLOR[2], HEAT_COLOR[3] * heat as f32];
+        rectangle(color, [0.0, 0.0, cell_width, cell_height], xform, gl);
+      }
+      draw_text(&cell.visits.to_string(), &resources.white_font, xform, gl);
+    }
+    
+    let pixel_size = SPRITE_PIXEL_SIZE as f64;
+    for f_pos in &telemetry.deaths {
+      let x = (f_pos[0] + 0.5) * cell_width  - pixel_size;
+      let y = (f_pos[1] + 0.5) * cell_height - pixel_size;
+      rectangle(DEATH_COLOR, [x, y, 2.0 * pixel_size, 2.0 * pixel_size], c.transform, gl);
+    }
+    
+    let total_duration: Seconds = telemetry.cells.values().map(|cell| cell.duration).sum();
+    let legend = format!("\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n Level {}: {} deaths, {:.0}s", level_number, telemetry.deaths.len(), total_duration);
+    draw_text(&legend, &resources.white_font, c.transform, gl);
+  });
+}
+
+pub fn render(
+  state: &State,
+  args: &piston::input::RenderArgs,
+  resources: &Resources,
+  dev_errors: &Vec<String>,
+  netplay_error: &Option<String>,
+  time_control: &TimeControl,
+  gl: &mut GlGraphics
+) {
+  gl.draw(args.viewport(), |c, gl| {
+    clear(resources.theme.clear_color, gl);
+    
+    // the room shakes, but not the text
+    let shake_offset = if state.effects {
+                         compute_shake_offset(state.shake_t0, state.time)
+                       } else {
+                         [0.0, 0.0]
+                       };
+    let transform = c.transform.trans(shake_offset[0] * SPRITE_PIXEL_SIZE as f64, shake_offset[1] * SPRITE_PIXEL_SIZE as f64);
     
     let show_next_level = if state.time % TRANSITION_FLASH_DURATION < TRANSITION_HALF_FLASH_DURATION {
                             if let Some(next_level) = state.next_level {
@@ -193,17 +476,43 @@ pub fn render(state: &State, args: &piston::input::RenderArgs, resources: &Resou
     } else {
       draw_lower_level(state.level_number, resources, transform, gl);
       draw_entities(state, resources, transform, gl);
+      if state.memory_overlay {
+        draw_memory_overlay(state.level_number, resources, transform, gl);
+      }
       draw_upper_level(state.level_number, resources, transform, gl);
     }
+    if state.effects {
+      draw_particles(&state.particles, state.time, transform, gl);
+    }
+    if state.inspector_overlay {
+      draw_inspector_grid(resources, transform, gl);
+      draw_inspector_text(state, resources, c.transform, gl);
+    }
     
-    let level_text = format!("\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n Level {}", state.level_number);
+    let mut level_text = format!("\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n Level {}", state.level_number);
+    if let Some(label) = time_control_label(time_control) {
+      level_text.push_str(&format!(" ({})", label));
+    }
     draw_text(&level_text, &resources.white_font, c.transform, gl);
     
     for message in state.message {
-      // Fade to white to make the text more readable
-      rectangle([1.0, 1.0, 1.0, 0.8], [0.0, 0.0, args.width as f64, args.height as f64], c.transform, gl);
+      // Fade the room to make the text more readable
+      rectangle(resources.theme.overlay_color, [0.0, 0.0, args.width as f64, args.height as f64], c.transform, gl);
       
-      draw_text(message, &resources.big_font, c.transform, gl);
+      // The box is centered in the window, aligned to the pixel grid
+      let font = &resources.big_font;
+      let max_columns = (args.width as f64 / font.sprite_width) as usize;
+      let lines = layout_message_box(&message_box(&resources.catalog, state, message), max_columns);
+      let dx = ((args.width as f64 - lines[0].chars().count() as f64 * font.sprite_width) / 2.0).floor();
+      let dy = ((args.height as f64 - lines.len() as f64 * font.sprite_height) / 2.0).floor().max(0.0);
+      draw_text(&lines.join("\n"), font, c.transform.trans(dx, dy), gl);
+    }
+    
+    if let Some(ref netplay_error) = *netplay_error {
+      draw_netplay_error(netplay_error, args, resources, c.transform, gl);
+    }
+    if !dev_errors.is_empty() {
+      draw_dev_errors(dev_errors, args, resources, c.transform, gl);
     }
   });
 }
@@ -0,0 +1,243 @@
+use std::fs::File;
+use std::io::{ Read, Write };
+
+use serialization::*;
+use settings::*;
+use state::*;
+use types::*;
+use types::RawInputEvent::*;
+
+
+// Every raw input event of a session, including the passage of time, so replaying them in order from the state in
+// which the session started reproduces the session exactly.
+pub struct Replay {
+  pub initial_state: Option<State>, // None in the replays recorded before it was saved, which start a new game
+  pub events: Vec<RawInputEvent>,
+}
+
+// The second player's keys end with "-2", the first player's keep the names they had before co-op mode.
+const EVENT_NAMES: &'static [(RawInputEvent, &'static str)] = &[
+  (PressUp(0),      "press-up"),
+  (ReleaseUp(0),    "release-up"),
+  (PressLeft(0),    "press-left"),
+  (ReleaseLeft(0),  "release-left"),
+  (PressDown(0),    "press-down"),
+  (ReleaseDown(0),  "release-down"),
+  (PressRight(0),   "press-right"),
+  (ReleaseRight(0), "release-right"),
+  (PressUp(1),      "press-up-2"),
+  (ReleaseUp(1),    "release-up-2"),
+  (PressLeft(1),    "press-left-2"),
+  (ReleaseLeft(1),  "release-left-2"),
+  (PressDown(1),    "press-down-2"),
+  (ReleaseDown(1),  "release-down-2"),
+  (PressRight(1),   "press-right-2"),
+  (ReleaseRight(1), "release-right-2"),
+  (PressPause,      "press-pause"),
+  (PressMemory,     "press-memory"),
+  (PressInspector,  "press-inspector"),
+  (PressAssists,    "press-assists"),
+  (PressAnyKey,     "press-any-key"),
+];
+
+
+// The name of an event other than TimePasses, as in the replay files.
+pub fn event_name(event: RawInputEvent) -> &'static str {
+  let &(_, name) = EVENT_NAMES.iter().find(|&&(e, _)| e == event).unwrap();
+  name
+}
+
+pub fn named_event(name: &str) -> Option<RawInputEvent> {
+  EVENT_NAMES.iter().find(|&&(_, n)| n == name).map(|&(event, _)| event)
+}
+
+pub fn new_replay(initial_state: &State) -> Replay {
+  Replay {
+    initial_state: Some(initial_state.clone()),
+    events: Vec::new(),
+  }
+}
+
+pub fn replay_duration(replay: &Replay) -> Seconds {
+  replay.events.iter().map(|event| match *event {
+    TimePasses(dt) => dt,
+    _              => 0.0,
+  }).sum()
+}
+
+// A replay looks like this, where the initial state is in the same JSON format as the "--dump" files, and
+// "wait 120 0.008333333333333333" stands for 120 consecutive TimePasses events of that duration:
+//
+//   # comment
+//   state
+//   {
+//     "version": 3,
+//     ...
+//   }
+//   end-state
+//   wait 120 0.008333333333333333
+//   press-right
+//   wait 31 0.008333333333333333
+//   release-right
+pub fn format_replay(replay: &Replay) -> String {
+  let mut text = String::new();
+  if let Some(ref initial_state) = replay.initial_state {
+    text.push_str("state\n");
+    text.push_str(&state_to_json(initial_state));
+    text.push_str("end-state\n");
+  }
+  
+  let mut i = 0;
+  while i < replay.events.len() {
+    match replay.events[i] {
+      TimePasses(dt) => {
+        let mut count = 0;
+        while i < replay.events.len() && replay.events[i] == TimePasses(dt) {
+          count += 1;
+          i += 1;
+        }
+        
+        // {:?} prints enough digits to parse back the exact same f64
+        text.push_str(&format!("wait {} {:?}\n", count, dt));
+      },
+      event          => {
+        text.push_str(event_name(event));
+        text.push('\n');
+        i += 1;
+      },
+    }
+  }
+  
+  text
+}
+
+fn parse_word<A: ::std::str::FromStr>(word: Option<&str>, what: &str) -> Result<A, String> {
+  word.and_then(|word| word.parse().ok()).ok_or(format!("syntax error in replay: expected {}", what))
+}
+
+pub fn parse_replay(text: &str) -> Result<Replay, String> {
+  let mut initial_state = None;
+  let mut events = Vec::new();
+  
+  let mut lines = text.lines();
+  while let Some(line) = lines.next() {
+    let line = line.split('#').next().unwrap();
+    let mut words = line.split_whitespace();
+    
+    match words.next() {
+      None          => {},
+      Some("state") => {
+        // the JSON lines are kept whole, since a '#' may be part of a script's text
+        let mut json = String::new();
+        loop {
+          match lines.next() {
+            Some(line) if line.trim() == "end-state" => break,
+            Some(line)                               => {
+              json.push_str(line);
+              json.push('\n');
+            },
+            None                                     => return Err("syntax error in replay: expected end-state".to_string()),
+          }
+        }
+        initial_state = Some(state_from_json(&json)?);
+      },
+      Some("wait")  => {
+        let count: usize = parse_word(words.next(), "a number of steps")?;
+        let dt: Seconds = parse_word(words.next(), "a duration")?;
+        for _ in 0..count {
+          events.push(TimePasses(dt));
+        }
+      },
+      Some(word)    => {
+        let event = named_event(word).ok_or(format!("syntax error in replay: unknown event {:?}", word))?;
+        events.push(event);
+      },
+    }
+  }
+  
+  Ok(Replay {
+    initial_state: initial_state,
+    events: events,
+  })
+}
+
+pub fn load_replay(path: &str) -> Result<Replay, String> {
+  let mut text = String::new();
+  File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+                  .map_err(|err| format!("could not read {}: {}", path, err))?;
+  
+  parse_replay(&text).map_err(|err| format!("{} in {}", err, path))
+}
+
+pub fn save_replay(replay: &Replay, path: &str) -> Result<(), String> {
+  File::create(path).and_then(|mut file| file.write_all(format_replay(replay).as_bytes()))
+                    .map_err(|err| format!("could not write {}: {}", path, err))
+}
+
+// "--record FILE" saves the session to FILE when the game exits, so it can be captured with "--capture FILE".
+pub fn record_setting() -> Option<String> {
+  command_line_option("record")
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use audio::*;
+  use game::*;
+  use script::*;
+  
+  
+  // Irregular frames, some of them long, and both players' keys, from the middle of level 4.
+  fn recorded_session() -> (Replay, State) {
+    let scripts = load_scripts();
+    let mut audio = NullAudio;
+    let mut state = level_state(4);
+    set_player_count(&mut state, 2);
+    let mut replay = new_replay(&state);
+    
+    let frame_durations = [1.0 / 60.0, 1.0 / 23.0, 1.0 / 144.0];
+    for frame in 0..400 {
+      let mut events = vec![TimePasses(frame_durations[frame % frame_durations.len()])];
+      match frame % 40 {
+        10 => events.push(PressRight(0)),
+        12 => events.push(ReleaseRight(0)),
+        25 => events.push(PressDown(1)),
+        30 => events.push(ReleaseDown(1)),
+        _  => {},
+      }
+      for event in events {
+        update(&mut state, &scripts, &mut audio, event);
+        replay.events.push(event);
+      }
+    }
+    
+    (replay, state)
+  }
+  
+  #[test]
+  fn replays_reproduce_the_session() {
+    let (replay, recorded_state) = recorded_session();
+    let text = format_replay(&replay);
+    let parsed = parse_replay(&text).unwrap();
+    assert_eq!(format_replay(&parsed), text);
+    
+    let scripts = load_scripts();
+    let mut audio = NullAudio;
+    let mut state = parsed.initial_state.unwrap();
+    for event in parsed.events {
+      update(&mut state, &scripts, &mut audio, event);
+    }
+    assert_eq!(state_to_binary(&state), state_to_binary(&recorded_state));
+  }
+  
+  #[test]
+  fn malformed_replays_are_rejected() {
+    assert!(parse_replay("# a new game\nwait 2 0.5\npress-right\n").unwrap().initial_state.is_none());
+    assert!(parse_replay("press-jump").is_err());
+    assert!(parse_replay("wait many 0.5").is_err());
+    assert!(parse_replay("wait 2").is_err());
+    assert!(parse_replay("state\n{}\nend-state").is_err());
+    assert!(parse_replay("state\n{\"version\": 3,").is_err());
+  }
+}
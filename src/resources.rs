@@ -1,56 +1,66 @@
 use opengl_graphics::Texture;
 
+use animation::*;
+use assets::*;
 use font::*;
+use i18n::*;
+use theme::*;
 
 
 pub const SPRITE_WIDTH:  u32 = 10;
 pub const SPRITE_HEIGHT: u32 = 10;
 
 pub struct Resources {
+  pub catalog:       Catalog,
+  pub theme:         Theme,
   pub big_font:      Font,
   pub small_font:    Font,
   pub white_font:    Font,
+  pub conveyor:      Texture,
   pub floor:         Texture,
   pub goal_top:      Texture,
   pub goal:          Texture,
-  pub player:        Texture,
+  pub ice:           Texture,
+  pub player:        SpriteSheet,
   pub sign:          Texture,
-  pub spiny:         Texture,
+  pub sink:          Texture,
+  pub spawner:       Texture,
+  pub spiny:         SpriteSheet,
   pub start_top:     Texture,
   pub start:         Texture,
   pub wall:          Texture,
 }
 
-pub fn load_resources() -> Resources {
-  use std::path::Path;
+fn load_texture(theme: &Theme, file_name: &str) -> Result<Texture, String> {
+  load_texture_asset(&image_path(theme, file_name))
+}
+
+// "player.png" and its descriptor, "player.txt"
+fn load_theme_sprite_sheet(theme: &Theme, name: &str) -> Result<SpriteSheet, String> {
+  load_sprite_sheet(&image_path(theme, &format!("{}.png", name)), &image_path(theme, &format!("{}.txt", name)))
+}
+
+pub fn load_resources() -> Result<Resources, String> {
+  let theme = load_theme(theme_setting());
   
-  Resources {
-    big_font:      Font {
-                     texture: Texture::from_path(Path::new("images/big-font.png")).unwrap(),
-                     zeroth_char: '\x00', grid_width: 25,
-                     cell_width:   20.0, cell_height:   20.0,
-                     sprite_width: 10.0, sprite_height: 20.0,
-                   },
-    small_font:    Font {
-                     texture: Texture::from_path(Path::new("images/small-font.png")).unwrap(),
-                     zeroth_char: '-', grid_width: 13,
-                     cell_width:   5.0, cell_height:   5.0,
-                     sprite_width: 5.0, sprite_height: 5.0,
-                   },
-    white_font:    Font {
-                     texture: Texture::from_path(Path::new("images/white-font.png")).unwrap(),
-                     zeroth_char: '\x00', grid_width: 25,
-                     cell_width:   20.0, cell_height:   20.0,
-                     sprite_width: 10.0, sprite_height: 20.0,
-                   },
-    floor:         Texture::from_path(Path::new("images/floor.png")).unwrap(),
-    goal_top:      Texture::from_path(Path::new("images/goal-top.png")).unwrap(),
-    goal:          Texture::from_path(Path::new("images/goal.png")).unwrap(),
-    player:        Texture::from_path(Path::new("images/player.png")).unwrap(),
-    sign:          Texture::from_path(Path::new("images/sign.png")).unwrap(),
-    spiny:         Texture::from_path(Path::new("images/spiny.png")).unwrap(),
-    start_top:     Texture::from_path(Path::new("images/start-top.png")).unwrap(),
-    start:         Texture::from_path(Path::new("images/start.png")).unwrap(),
-    wall:          Texture::from_path(Path::new("images/wall.png")).unwrap(),
-  }
+  Ok(Resources {
+    catalog:       load_catalog(&language_setting()),
+    big_font:      load_font(&image_path(&theme, "big-font.png"))?,
+    small_font:    load_font(&image_path(&theme, "small-font.png"))?,
+    white_font:    load_font(&image_path(&theme, "white-font.png"))?,
+    conveyor:      load_texture(&theme, "conveyor.png")?,
+    floor:         load_texture(&theme, "floor.png")?,
+    goal_top:      load_texture(&theme, "goal-top.png")?,
+    goal:          load_texture(&theme, "goal.png")?,
+    ice:           load_texture(&theme, "ice.png")?,
+    player:        load_theme_sprite_sheet(&theme, "player")?,
+    sign:          load_texture(&theme, "sign.png")?,
+    sink:          load_texture(&theme, "sink.png")?,
+    spawner:       load_texture(&theme, "spawner.png")?,
+    spiny:         load_theme_sprite_sheet(&theme, "spiny")?,
+    start_top:     load_texture(&theme, "start-top.png")?,
+    start:         load_texture(&theme, "start.png")?,
+    wall:          load_texture(&theme, "wall.png")?,
+    theme:         theme,
+  })
 }
@@ -0,0 +1,694 @@
+use audio::*;
+use game::*;
+use inspector::*;
+use levels::*;
+use script::*;
+use state::*;
+use terminal::*;
+use types::*;
+use types::AnimatedPos::*;
+use types::Message::*;
+use types::RawInputEvent::*;
+
+
+// Regression tests for the gameplay, written like this:
+//
+//   # comment
+//   level 4            # start over in level 4, as if the player had just entered it
+//   press right x3     # wait until the player stands still, then tap the key, three times
+//   wait 0.5           # in seconds, in frames of 1/60 seconds
+//   expect deaths 1
+//
+// The other commands are "hold DIR", "release DIR", "pause", "assists", "any-key" and "wait N in Mms frames",
+// which waits in frames of M milliseconds as on a slow machine, plus "coop", which adds a second player, and
+// "player N", which makes the next commands and expectations about player N. The other expectations are
+// "idle X Y", "moving X Y DIR", "corpses N", "level N", "message none|title|paused|the-end|assists|sign N",
+// "page N", "flag NAME", "spinies N" and "spiny X Y [DIR]". A failed expectation shows the room as in the terminal.
+pub struct Scenario {
+  pub state: State,
+  pub audio: RecordingAudio,
+  scripts: Scripts,
+  player_index: PlayerIndex, // the player which the commands are about
+}
+
+const FRAME_DURATION: Seconds = 1.0 / 60.0;
+const MAX_IDLE_WAIT: Seconds = 10.0;
+
+
+pub fn new_scenario(level_number: LevelNumber) -> Scenario {
+  Scenario {
+    state: level_state(level_number),
+    audio: RecordingAudio::new(),
+    scripts: load_scripts(),
+    player_index: 0,
+  }
+}
+
+pub fn run_scenario(text: &str) -> Scenario {
+  let mut scenario = new_scenario(min_level());
+  continue_scenario(&mut scenario, text);
+  
+  scenario
+}
+
+// Replaces a level with a level file's contents, e.g. to test signs which the real levels don't have. The tests run in
+// separate threads, and the replacement only affects the current thread.
+pub fn load_test_level(level_number: LevelNumber, text: &str) {
+  let level_description = parse_level_description(text).unwrap();
+  set_level_override(level_number, Some(Box::leak(Box::new(level_description))));
+  check_level(level_number).unwrap();
+}
+
+pub fn continue_scenario(scenario: &mut Scenario, text: &str) {
+  for (line_index, line) in text.lines().enumerate() {
+    let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+    if words.is_empty() {
+      continue;
+    }
+    
+    if let Err(err) = run_command(scenario, &words) {
+      panic!("scenario line {}: {}\n  {}\n\n{}", line_index + 1, line.trim(), err, describe_room(&scenario.state));
+    }
+  }
+}
+
+
+fn send(scenario: &mut Scenario, raw_input_event: RawInputEvent) {
+  update(&mut scenario.state, &scenario.scripts, &mut scenario.audio, raw_input_event);
+}
+
+fn wait(scenario: &mut Scenario, seconds: Seconds, frame_duration: Seconds) {
+  let mut remaining = seconds;
+  while remaining > 0.0 {
+    let dt = remaining.min(frame_duration);
+    send(scenario, TimePasses(dt));
+    remaining -= dt;
+  }
+}
+
+// Like a player who waits for the previous move to finish before tapping the next key.
+fn wait_until_idle(scenario: &mut Scenario) -> Result<(), String> {
+  let mut waited = 0.0;
+  loop {
+    match scenario.state.players[scenario.player_index].pos {
+      Idle(_) => return Ok(()),
+      _       => {},
+    }
+    
+    if waited >= MAX_IDLE_WAIT {
+      return Err(format!("the player was still moving after {} seconds", MAX_IDLE_WAIT));
+    }
+    wait(scenario, FRAME_DURATION, FRAME_DURATION);
+    waited += FRAME_DURATION;
+  }
+}
+
+fn parse_word<A: ::std::str::FromStr>(word: Option<&&str>, what: &str) -> Result<A, String> {
+  word.and_then(|word| word.parse().ok()).ok_or(format!("syntax error in scenario: expected {}", what))
+}
+
+fn parse_pos(x: Option<&&str>, y: Option<&&str>) -> Result<Pos, String> {
+  Ok([parse_word(x, "an x coordinate")?, parse_word(y, "a y coordinate")?])
+}
+
+fn parse_dir(word: Option<&&str>) -> Result<Dir, String> {
+  match word.map(|word| *word) {
+    Some("up")    => Ok(UP),
+    Some("left")  => Ok(LEFT),
+    Some("down")  => Ok(DOWN),
+    Some("right") => Ok(RIGHT),
+    _             => Err("syntax error in scenario: expected up, left, down or right".to_string()),
+  }
+}
+
+fn press_and_release(player_index: PlayerIndex, dir: Dir) -> (RawInputEvent, RawInputEvent) {
+  match dir {
+    UP    => (PressUp(player_index),    ReleaseUp(player_index)),
+    LEFT  => (PressLeft(player_index),  ReleaseLeft(player_index)),
+    DOWN  => (PressDown(player_index),  ReleaseDown(player_index)),
+    _     => (PressRight(player_index), ReleaseRight(player_index)),
+  }
+}
+
+// e.g. "in 150ms frames"
+fn parse_frame_duration(words: &[&str]) -> Result<Seconds, String> {
+  if words.is_empty() {
+    return Ok(FRAME_DURATION);
+  }
+  
+  let error = format!("syntax error in scenario: expected in Nms frames, not {:?}", words.join(" "));
+  if words.len() != 3 || words[0] != "in" || words[2] != "frames" || !words[1].ends_with("ms") {
+    return Err(error);
+  }
+  match words[1][..words[1].len() - 2].parse::<f64>() {
+    Ok(milliseconds) if milliseconds > 0.0 => Ok(milliseconds / 1000.0),
+    _                                      => Err(error),
+  }
+}
+
+// "x3" repeats the command three times
+fn parse_repetitions(word: Option<&&str>) -> Result<usize, String> {
+  match word {
+    None       => Ok(1),
+    Some(word) => {
+      if word.starts_with('x') {
+        word[1..].parse().map_err(|_| format!("syntax error in scenario: expected xN, not {:?}", word))
+      } else {
+        Err(format!("syntax error in scenario: expected xN, not {:?}", word))
+      }
+    },
+  }
+}
+
+fn parse_message(words: &[&str], level_number: LevelNumber) -> Result<Option<Message>, String> {
+  match words.first().map(|word| *word) {
+    Some("none")    => Ok(None),
+    Some("title")   => Ok(Some(TitleM)),
+    Some("paused")  => Ok(Some(PausedM)),
+    Some("the-end") => Ok(Some(TheEndM)),
+    Some("assists") => Ok(Some(AssistsM)),
+    Some("sign")    => Ok(Some(SignM(level_number, parse_word(words.get(1), "a sign number")?))),
+    _               => Err("syntax error in scenario: expected none, title, paused, the-end, assists or sign N".to_string()),
+  }
+}
+
+fn check<A: PartialEq + ::std::fmt::Debug>(what: &str, expected: A, actual: A) -> Result<(), String> {
+  if expected == actual {
+    Ok(())
+  } else {
+    Err(format!("expected {} {:?}, got {:?}", what, expected, actual))
+  }
+}
+
+fn run_command(scenario: &mut Scenario, words: &[&str]) -> Result<(), String> {
+  match words[0] {
+    "level"   => {
+      let level_number = parse_word(words.get(1), "a level number")?;
+      if level_number < min_level() || level_number > max_level() {
+        return Err(format!("there is no level {}", level_number));
+      }
+      let player_count = scenario.state.players.len();
+      scenario.state = level_state(level_number);
+      set_player_count(&mut scenario.state, player_count);
+    },
+    "coop"    => set_player_count(&mut scenario.state, 2),
+    "player"  => {
+      let player_number: PlayerIndex = parse_word(words.get(1), "a player number")?;
+      if player_number < 1 || player_number > scenario.state.players.len() {
+        return Err(format!("there is no player {}", player_number));
+      }
+      scenario.player_index = player_number - 1;
+    },
+    "press"   => {
+      let (press, release) = press_and_release(scenario.player_index, parse_dir(words.get(1))?);
+      for _ in 0..parse_repetitions(words.get(2))? {
+        wait_until_idle(scenario)?;
+        send(scenario, press);
+        send(scenario, release);
+      }
+    },
+    "hold"    => send(scenario, press_and_release(scenario.player_index, parse_dir(words.get(1))?).0),
+    "release" => send(scenario, press_and_release(scenario.player_index, parse_dir(words.get(1))?).1),
+    "pause"   => send(scenario, PressPause),
+    "assists" => send(scenario, PressAssists),
+    "any-key" => send(scenario, PressAnyKey),
+    "wait"    => {
+      let seconds = parse_word(words.get(1), "a number of seconds")?;
+      let frame_duration = parse_frame_duration(&words[2..])?;
+      wait(scenario, seconds, frame_duration);
+    },
+    "expect"  => run_expectation(scenario, &words[1..])?,
+    word      => return Err(format!("syntax error in scenario: unknown command {:?}", word)),
+  }
+  
+  Ok(())
+}
+
+fn run_expectation(scenario: &Scenario, words: &[&str]) -> Result<(), String> {
+  let state = &scenario.state;
+  
+  match words.first().map(|word| *word) {
+    Some("idle")    => {
+      let pos = parse_pos(words.get(1), words.get(2))?;
+      match state.players[scenario.player_index].pos {
+        Idle(actual) if actual == pos => Ok(()),
+        _                             => Err(format!("expected the player to be idle at {:?}", pos)),
+      }
+    },
+    Some("moving")  => {
+      let pos = parse_pos(words.get(1), words.get(2))?;
+      let dir = parse_dir(words.get(3))?;
+      match state.players[scenario.player_index].pos {
+        MovingSince(actual_pos, actual_dir, _) if actual_pos == pos && actual_dir == dir => Ok(()),
+        _ => Err(format!("expected the player to be moving {} from {:?}", dir_name(dir), pos)),
+      }
+    },
+    Some("deaths")  => {
+      let deaths = scenario.audio.sounds.iter().filter(|&&sound| sound == Sound::Death).count();
+      check("deaths", parse_word(words.get(1), "a number of deaths")?, deaths)
+    },
+    Some("corpses") => check("corpses", parse_word(words.get(1), "a number of corpses")?, state.corpses.len()),
+    Some("level")   => check("level", parse_word(words.get(1), "a level number")?, state.level_number),
+    Some("message") => check("message", parse_message(&words[1..], state.level_number)?, state.message),
+    Some("page")    => check("page", parse_word(words.get(1), "a page number")?, state.message_page + 1),
+    Some("flag")    => {
+      let flag = words.get(1).ok_or("syntax error in scenario: expected a flag".to_string())?;
+      if state.flags.contains(flag) {
+        Ok(())
+      } else {
+        Err(format!("expected the flag {:?} to be set", flag))
+      }
+    },
+    Some("spinies") => check("spinies", parse_word(words.get(1), "a number of spinies")?, state.spinies.len()),
+    Some("spiny")   => {
+      let pos = parse_pos(words.get(1), words.get(2))?;
+      let dir = match words.get(3) {
+        Some(_) => Some(parse_dir(words.get(3))?),
+        None    => None,
+      };
+      
+      let found = state.spinies.iter().any(|spiny| spiny.pos == pos && dir.map_or(true, |dir| spiny.dir == dir));
+      if found {
+        Ok(())
+      } else {
+        Err(format!("expected a spiny at {:?}{}", pos, dir.map_or(String::new(), |dir| format!(" moving {}", dir_name(dir)))))
+      }
+    },
+    _               => Err("syntax error in scenario: expected idle, moving, deaths, corpses, level, message, page, flag, spinies or spiny".to_string()),
+  }
+}
+
+
+// The room as in the terminal, below the details which the ASCII version can't show.
+pub fn describe_room(state: &State) -> String {
+  let mut text = format!("level {}\n", state.level_number);
+  for line in inspector_lines(state).into_iter().chain(ascii_room(state)) {
+    text.push_str(&line);
+    text.push('\n');
+  }
+  
+  text
+}
+
+
+#[test]
+fn the_player_walks_one_cell_per_tap() {
+  run_scenario("
+    level 1
+    wait 0.5
+    expect idle 0 1
+    press right x2
+    expect moving 1 1 right
+    wait 0.25
+    expect idle 2 1
+  ");
+}
+
+#[test]
+fn plain_signs_are_read_while_the_key_is_held() {
+  run_scenario("
+    level 1
+    press right x4
+    press down
+    wait 0.3
+    hold down
+    expect idle 4 2
+    expect message sign 0
+    release down
+    expect message none
+  ");
+}
+
+#[test]
+fn longer_signs_are_read_page_by_page() {
+  run_scenario("
+    level 4
+    press right
+    hold down
+    wait 1.5
+    expect idle 1 4
+    expect message sign 0
+    expect page 1
+    release down
+    expect message sign 0
+    any-key
+    expect page 2
+    any-key
+    expect message none
+  ");
+}
+
+// The real levels don't use the choices, so this room does.
+pub const SIGNS_ROOM: &'static str = concat!(
+  " . . . . . . . . . .\n",
+  ".##################.\n",
+  ".LD            ####.\n",
+  ".##              ##.\n",
+  ".##    ##S0##S1  ##.\n",
+  ".##              ##.\n",
+  ".##              RD.\n",
+  ".##################.\n",
+  "sign 0 choices brave careful\n",
+  "sign 1 if careful pages 2\n",
+);
+#[test]
+fn choices_set_flags() {
+  load_test_level(min_level(), SIGNS_ROOM);
+  run_scenario("
+    level 1
+    press right x4
+    press down
+    wait 0.3
+    hold down
+    expect message sign 0
+    release down
+    expect message sign 0
+    press down
+    press up
+    press up
+    any-key
+    expect message none
+    expect flag careful
+  ");
+}
+
+#[test]
+fn flags_change_the_pages_of_later_signs() {
+  load_test_level(min_level(), SIGNS_ROOM);
+  let mut scenario = run_scenario("
+    level 1
+    press right x6
+    press down
+    wait 0.3
+    hold down
+    expect message sign 1
+    release down
+    expect message none
+    press left x2
+    wait 0.3
+    hold down
+    expect message sign 0
+    release down
+    press down
+    any-key
+    expect flag careful
+    press right x2
+    wait 0.3
+    hold down
+    expect message sign 1
+    release down
+    expect message sign 1
+    any-key
+    expect page 2
+    any-key
+    expect message none
+  ");
+  assert!(!scenario.state.flags.contains("brave"));
+  
+  // without the flag, the sign has a single page again
+  scenario.state.flags.clear();
+  continue_scenario(&mut scenario, "
+    hold down
+    expect message sign 1
+    any-key
+    expect message none
+  ");
+}
+
+const ICE_ROOM: &'static str = concat!(
+  " . . . . . . . . . .\n",
+  ".##################.\n",
+  ".LD~~~~~~  ~~~~~~##.\n",
+  ".##              ##.\n",
+  ".##              ##.\n",
+  ".##              ##.\n",
+  ".##          ~~~~RD.\n",
+  ".##################.\n",
+);
+
+#[test]
+fn the_player_slides_on_ice_until_the_floor_or_a_wall() {
+  load_test_level(min_level(), ICE_ROOM);
+  let scenario = run_scenario("
+    level 1
+    press right
+    wait 1.5
+    expect idle 4 1
+    press right
+    wait 1.0
+    expect idle 7 1
+  ");
+  
+  // sliding into a wall is not bumping into it
+  assert!(!scenario.audio.sounds.contains(&Sound::Bump));
+}
+
+#[test]
+fn sliding_into_a_door_stops_in_front_of_it() {
+  load_test_level(min_level(), ICE_ROOM);
+  run_scenario("
+    level 1
+    press right
+    press down x4
+    press right
+    press right
+    wait 1.0
+    expect idle 8 5
+    expect level 1
+    press right
+    wait 1.5
+    expect level 2
+  ");
+}
+
+const CONVEYOR_ROOM: &'static str = concat!(
+  " . . . . . . . . . .\n",
+  ".##################.\n",
+  ".LDC>C>C>        ##.\n",
+  ".##              ##.\n",
+  ".##    C>C>##    ##.\n",
+  ".##              ##.\n",
+  ".##        C>C>C>RD.\n",
+  ".##################.\n",
+);
+
+#[test]
+fn conveyors_push_the_player_until_the_floor_or_a_wall() {
+  load_test_level(min_level(), CONVEYOR_ROOM);
+  let scenario = run_scenario("
+    level 1
+    press right
+    wait 1.5
+    expect idle 4 1
+    press down
+    press left
+    press down
+    wait 1.0
+    expect idle 4 3
+  ");
+  
+  // the conveyor keeps pushing against the wall, silently
+  assert!(!scenario.audio.sounds.contains(&Sound::Bump));
+}
+
+#[test]
+fn players_walking_against_a_conveyor_are_pushed_back() {
+  load_test_level(min_level(), CONVEYOR_ROOM);
+  run_scenario("
+    level 1
+    press right
+    press down
+    press left
+    press down
+    wait 1.0
+    expect idle 4 3
+    press left
+    expect moving 4 3 left
+    wait 0.3
+    expect moving 3 3 right
+    press up
+    wait 0.3
+    expect idle 4 2
+  ");
+}
+
+#[test]
+fn conveyors_push_the_player_into_the_door_but_not_through_it() {
+  load_test_level(min_level(), CONVEYOR_ROOM);
+  run_scenario("
+    level 1
+    press right
+    press down
+    press left
+    press down
+    press down
+    press right
+    press down
+    wait 1.5
+    expect idle 8 5
+    expect level 1
+    press right
+    wait 1.5
+    expect level 2
+  ");
+}
+
+#[test]
+fn walking_into_a_spiny_column_is_deadly() {
+  run_scenario("
+    level 4
+    expect spinies 6
+    expect spiny 2 5 up
+    press right x4
+    wait 0.5
+    expect deaths 1
+    expect corpses 1
+    wait 1.0
+    expect corpses 0
+  ");
+}
+
+// The spiny in the middle bounces off the one on its right at the start of the first move, then off the one on its
+// left, which is coming back from the wall, halfway through it. When the collisions were checked on every frame
+// instead of once per move, it bounced back and forth on each frame until the middle of the move, so where it went
+// depended on the frame rate, and the spiny on the left could end up inside the wall.
+const BOUNCING_ROOM: &'static str = concat!(
+  " . . . . . . . . . .\n",
+  ".##################.\n",
+  ".LD              ##.\n",
+  ".##    ##  ##    ##.\n",
+  ".##    ^^>>^^    ##.\n",
+  ".##              ##.\n",
+  ".##              RD.\n",
+  ".##################.\n",
+);
+
+#[test]
+fn spinies_bounce_the_same_way_at_any_frame_rate() {
+  load_test_level(min_level(), BOUNCING_ROOM);
+  run_scenario("
+    level 1
+    wait 0.2
+    expect spiny 3 3 down
+    expect spiny 4 3 left
+    expect spiny 5 3 down
+    level 1
+    wait 0.2 in 40ms frames
+    expect spiny 3 3 down
+    expect spiny 4 3 left
+    expect spiny 5 3 down
+  ");
+}
+
+#[test]
+fn the_right_door_leads_to_the_next_level() {
+  run_scenario("
+    level 1
+    press right
+    hold down
+    wait 1.5
+    release down
+    expect idle 1 5
+    hold right
+    wait 3.0
+    release right
+    expect level 2
+  ");
+}
+
+#[test]
+fn invulnerable_players_walk_through_spiny_columns() {
+  run_scenario("
+    level 4
+    wait 0.5
+    assists
+    expect message assists
+    press down x2
+    pause
+    assists
+    expect message none
+    press right x4
+    wait 0.5
+    expect deaths 0
+    expect idle 4 1
+  ");
+}
+
+#[test]
+fn both_players_must_go_through_the_door() {
+  run_scenario("
+    level 1
+    coop
+    press right
+    hold down
+    wait 1.5
+    release down
+    hold right
+    wait 3.0
+    release right
+    expect level 1
+    player 2
+    press right
+    hold down
+    wait 1.5
+    release down
+    hold right
+    wait 3.0
+    release right
+    expect level 2
+    player 1
+    expect idle 0 1
+  ");
+}
+
+#[test]
+fn the_last_player_finishes_going_through_the_door() {
+  run_scenario("
+    level 1
+    coop
+    press right
+    hold down
+    player 2
+    press right
+    hold down
+    wait 1.5
+    release down
+    player 1
+    release down
+    expect idle 1 5
+    hold right
+    wait 0.25
+    player 2
+    hold right
+    wait 2.6        # the first player is through the door, the second one is one step behind
+    expect level 1
+    wait 0.3
+    expect level 2
+  ");
+}
+
+#[test]
+fn the_second_player_can_die_too() {
+  run_scenario("
+    level 4
+    coop
+    player 2
+    press right x4
+    wait 0.5
+    expect deaths 1
+    player 1
+    expect idle 0 1
+  ");
+}
+
+#[test]
+fn spinies_stay_in_the_room_on_slow_machines() {
+  run_scenario("
+    level 4
+    wait 3.0 in 150ms frames
+    expect spinies 6
+    expect spiny 2 5 up
+    expect spiny 4 1 down
+    expect spiny 6 1 up
+  ");
+}
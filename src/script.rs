@@ -0,0 +1,452 @@
+extern crate rhai;
+
+use std::cell::RefCell;
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::mem;
+use std::path::{ Path, PathBuf };
+use rhai::{ CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST };
+
+use assets::*;
+use i18n::*;
+use levels::*;
+use spiny::*;
+use state::*;
+use types::*;
+
+
+// Some rooms need one-off behaviour which doesn't fit in the ascii maps, e.g. a sign whose text changes after
+// the player dies three times. Each level may have a script at "scripts/level-N.rhai" defining callbacks named
+// after the Action variants, e.g.
+//
+//   fn on_die(x, y) {
+//     this.set("deaths", this.get("deaths") + 1);
+//   }
+//
+//   fn on_read_sign(key) {
+//     if this.get("deaths") >= 3 {
+//       this.message = "level-1-having-trouble";
+//     }
+//   }
+//
+// Messages are identified by their key in the catalogs, e.g. "level-4-sign-1", so they can be translated. A key
+// which isn't in the catalogs is displayed as-is.
+//
+// The callbacks run after the action has been executed, and `this` is a Room through which the script can read
+// and modify the parts of the State which are safe to modify. The spinies added or removed by a script only appear or
+// disappear once the spinies are aligned with the grid, so during a move, add_spiny and remove_spiny are about the
+// cells which the spinies are moving to, while has_spiny and spiny_count still see the spinies where they were.
+pub struct Scripts {
+  engine: Engine,
+  asts: HashMap<LevelNumber, AST>,
+}
+
+#[derive(Clone)]
+struct Room {
+  level: LevelNumber,
+  time: Seconds,
+  message: Option<Message>,
+  spinies: Vec<MovingSpiny>, // read-only, the changes are in spiny_changes
+  spiny_changes: Vec<SpinyChange>,
+  temporary_walls: Vec<TemporaryWall>,
+  variables: BTreeMap<String, i64>,
+  flags: HashSet<Flag>, // read-only, they are set by the player's choices
+}
+
+
+thread_local! {
+  static INTERNED_MESSAGES: RefCell<HashMap<String, Message>> = RefCell::new(HashMap::new());
+}
+
+// Messages must be Copy, so the keys built by the scripts must be leaked. We only leak each distinct key once.
+pub fn intern_message(key: String) -> Message {
+  INTERNED_MESSAGES.with(|interned_messages| {
+    let mut interned_messages = interned_messages.borrow_mut();
+    if let Some(message) = interned_messages.get(&key) {
+      return *message;
+    }
+    
+    let message = Message::ScriptM(Box::leak(key.clone().into_boxed_str()));
+    interned_messages.insert(key, message);
+    message
+  })
+}
+
+// Scripts use i64 coordinates, reject those which aren't inside the room.
+fn script_pos(x: i64, y: i64) -> Option<Pos> {
+  if x < 0 || y < 0 || x >= LEVEL_WIDTH as i64 || y >= LEVEL_HEIGHT as i64 {
+    None
+  } else {
+    let pos = [x as i8, y as i8];
+    if is_inside_room(pos) {
+      Some(pos)
+    } else {
+      None
+    }
+  }
+}
+
+fn script_dir(dx: i64, dy: i64) -> Option<Dir> {
+  match (dx, dy) {
+    ( 0,-1) => Some(UP),
+    (-1, 0) => Some(LEFT),
+    ( 0, 1) => Some(DOWN),
+    ( 1, 0) => Some(RIGHT),
+    _       => None,
+  }
+}
+
+
+fn register_room_api(engine: &mut Engine) {
+  engine.register_type_with_name::<Room>("Room");
+  
+  engine.register_get("level", |room: &mut Room| room.level as i64);
+  engine.register_get("time",  |room: &mut Room| room.time);
+  
+  // the empty string means "no message"
+  engine.register_get_set(
+    "message",
+    |room: &mut Room| room.message.map(message_key).unwrap_or(String::new()),
+    |room: &mut Room, key: String| {
+      room.message = if key.is_empty() {
+                       None
+                     } else {
+                       Some(intern_message(key))
+                     };
+    }
+  );
+  
+  // variables persist from one callback to the next and from one level to the next; unset variables are 0
+  engine.register_fn("get", |room: &mut Room, name: &str| {
+    *room.variables.get(name).unwrap_or(&0)
+  });
+  engine.register_fn("set", |room: &mut Room, name: &str, value: i64| {
+    room.variables.insert(name.to_string(), value);
+  });
+  
+  engine.register_fn("has_flag", |room: &mut Room, flag: &str| room.flags.contains(flag));
+  
+  engine.register_fn("has_wall", |room: &mut Room, x: i64, y: i64| {
+    room.temporary_walls.iter().any(|temporary_wall| Some(temporary_wall.pos) == script_pos(x, y))
+  });
+  engine.register_fn("add_wall", |room: &mut Room, x: i64, y: i64| {
+    if let Some(pos) = script_pos(x, y) {
+      if !room.temporary_walls.iter().any(|temporary_wall| temporary_wall.pos == pos) {
+        room.temporary_walls.push(
+          TemporaryWall {
+            pos: pos,
+            lifetime: Lifetime { level_min: room.level, level_max: room.level },
+          }
+        );
+      }
+    }
+  });
+  engine.register_fn("remove_wall", |room: &mut Room, x: i64, y: i64| {
+    let pos = script_pos(x, y);
+    room.temporary_walls.retain(|temporary_wall| Some(temporary_wall.pos) != pos);
+  });
+  
+  engine.register_fn("spiny_count", |room: &mut Room| room.spinies.len() as i64);
+  engine.register_fn("has_spiny", |room: &mut Room, x: i64, y: i64| {
+    room.spinies.iter().any(|spiny| Some(spiny.pos) == script_pos(x, y))
+  });
+  engine.register_fn("add_spiny", |room: &mut Room, x: i64, y: i64, dx: i64, dy: i64| {
+    if let (Some(pos), Some(dir)) = (script_pos(x, y), script_dir(dx, dy)) {
+      room.spiny_changes.push(SpinyChange::AddSpinyC(
+        MovingSpiny {
+          pos: pos,
+          dir: dir,
+          lifetime: Lifetime { level_min: room.level, level_max: room.level },
+          enabled: true,
+        }
+      ));
+    }
+  });
+  engine.register_fn("remove_spiny", |room: &mut Room, x: i64, y: i64| {
+    if let Some(pos) = script_pos(x, y) {
+      room.spiny_changes.push(SpinyChange::RemoveSpinyC(pos));
+    }
+  });
+}
+
+pub fn load_scripts() -> Scripts {
+  let (scripts, errors) = compile_scripts();
+  for err in errors {
+    eprintln!("{}", err);
+  }
+  
+  scripts
+}
+
+// A broken script shouldn't prevent the rest of the game from being played, so the scripts which compile are loaded
+// even if the others don't. The errors are returned so dev mode can display them.
+pub fn compile_scripts() -> (Scripts, Vec<String>) {
+  load_scripts_with(|relative_path| find_asset(relative_path).ok())
+}
+
+// `find` turns a path such as "scripts/level-1.rhai" into the path of the file, if there is one.
+fn load_scripts_with<F: Fn(&Path) -> Option<PathBuf>>(find: F) -> (Scripts, Vec<String>) {
+  let mut engine = Engine::new();
+  register_room_api(&mut engine);
+  
+  let mut asts = HashMap::new();
+  let mut errors = Vec::new();
+  for level_number in min_level()..max_level()+1 {
+    let relative_path = format!("scripts/level-{}.rhai", level_number);
+    if let Some(path) = find(Path::new(&relative_path)) {
+      match engine.compile_file(path.clone()) {
+        Ok(ast) => {
+          asts.insert(level_number, ast);
+        },
+        Err(err) => {
+          errors.push(format!("could not load {}: {}", path.display(), err));
+        },
+      }
+    }
+  }
+  
+  let scripts = Scripts {
+    engine: engine,
+    asts: asts,
+  };
+  
+  (scripts, errors)
+}
+
+
+fn callback_for(action: &Action) -> (&'static str, Vec<Dynamic>) {
+  use types::Action::*;
+  
+  let pos_args = |pos: Pos| vec![Dynamic::from(pos[0] as i64), Dynamic::from(pos[1] as i64)];
+  let dir_args = |dir: Dir| vec![Dynamic::from(dir[0] as i64), Dynamic::from(dir[1] as i64)];
+  
+  match *action {
+    Move(_, pos, dir) => ("on_move",           pos_args(pos).into_iter().chain(dir_args(dir)).collect()),
+    Slide(_, pos, dir) =>
+                         ("on_slide",          pos_args(pos).into_iter().chain(dir_args(dir)).collect()),
+    Bump              => ("on_bump",           vec![]),
+    ReadSign(message) => ("on_read_sign",      vec![Dynamic::from(message_key(message))]),
+    NextPage          => ("on_next_page",      vec![]),
+    HighlightChoice(choice_index) =>
+                         ("on_highlight_choice", vec![Dynamic::from(choice_index as i64)]),
+    Choose(flag)      => ("on_choose",         vec![Dynamic::from(flag.to_string())]),
+    Die(_, f_pos)     => ("on_die",            vec![Dynamic::from(f_pos[0]), Dynamic::from(f_pos[1])]),
+    PreviousLevel(_)  => ("on_previous_level", vec![]),
+    NextLevel(_)      => ("on_next_level",     vec![]),
+    TransitionLevel(level_src, level_dst) =>
+                         ("on_transition_level", vec![Dynamic::from(level_src as i64), Dynamic::from(level_dst as i64)]),
+    Pause             => ("on_pause",          vec![]),
+    Unpause           => ("on_unpause",        vec![]),
+    OpenAssists       => ("on_open_assists",   vec![]),
+    ChangeAssist(choice_index) =>
+                         ("on_change_assist",  vec![Dynamic::from(choice_index as i64)]),
+  }
+}
+
+fn call_script(scripts: &Scripts, ast: &AST, name: &str, args: Vec<Dynamic>, room: Room) -> Result<Room, Box<EvalAltResult>> {
+  let mut this = Dynamic::from(room);
+  {
+    let options = CallFnOptions::new().bind_this_ptr(&mut this);
+    let _ = scripts.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, name, args)?;
+  }
+  
+  Ok(this.cast::<Room>())
+}
+
+// Run the callback for an action which has just been executed.
+pub fn run_script(scripts: &Scripts, state: &mut State, action: &Action) {
+  // "THE END" is final, and TransitionLevel is retried on every frame until the spinies are aligned with the grid,
+  // we only want to notify the destination level once the transition has actually happened.
+  if state.frozen {
+    return;
+  }
+  if let Action::TransitionLevel(_, level_dst) = *action {
+    if state.level_number != level_dst {
+      return;
+    }
+  }
+  
+  let ast = match scripts.asts.get(&state.level_number) {
+    Some(ast) => ast,
+    None      => return,
+  };
+  let (name, args) = callback_for(action);
+  if !ast.iter_functions().any(|f| f.name == name && f.params.len() == args.len()) {
+    return;
+  }
+  
+  let room = Room {
+    level: state.level_number,
+    time: state.time,
+    message: state.message,
+    spinies: state.spinies.clone(),
+    spiny_changes: Vec::new(),
+    temporary_walls: mem::replace(&mut state.temporary_walls, Vec::new()),
+    variables: mem::replace(&mut state.script_variables, BTreeMap::new()),
+    flags: state.flags.clone(),
+  };
+  let backup = room.clone();
+  
+  // If the script fails, report the error and leave the State as it was before the callback.
+  let room = match call_script(scripts, ast, name, args, room) {
+    Ok(room) => room,
+    Err(err) => {
+      eprintln!("error in scripts/level-{}.rhai, {}: {}", state.level_number, name, err);
+      backup
+    },
+  };
+  
+  if room.message != state.message {
+    state.message = room.message;
+    state.message_page = 0;
+    state.selected_choice = 0;
+  }
+  state.temporary_walls = room.temporary_walls;
+  state.script_variables = room.variables;
+  
+  state.spiny_changes.extend(room.spiny_changes);
+  if state.time == state.spinies_moving_since {
+    apply_spiny_changes(&mut state.spinies, mem::replace(&mut state.spiny_changes, Vec::new()));
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use audio::*;
+  use game::*;
+  use std::env;
+  use std::fs::{ self, File };
+  use std::io::Write;
+  use std::process;
+  use types::Action::*;
+  use types::RawInputEvent::*;
+  
+  
+  fn scripts_for(level_number: LevelNumber, source: &str) -> Scripts {
+    let mut engine = Engine::new();
+    register_room_api(&mut engine);
+    let ast = engine.compile(source).unwrap();
+    
+    let mut asts = HashMap::new();
+    asts.insert(level_number, ast);
+    Scripts {
+      engine: engine,
+      asts: asts,
+    }
+  }
+  
+  
+  #[test]
+  fn callbacks_see_and_change_the_room() {
+    let scripts = scripts_for(4, "
+      fn on_bump() {
+        this.set(\"bumps\", this.get(\"bumps\") + 1);
+        this.set(\"level\", this.level);
+        this.set(\"careful\", if this.has_flag(\"careful\") { 1 } else { 0 });
+        this.set(\"spinies\", this.spiny_count());
+        if this.has_wall(7, 2) {
+          this.remove_wall(7, 2);
+        } else {
+          this.add_wall(7, 2);
+          this.add_wall(0, 0); // not inside the room
+        }
+        if this.message == \"\" {
+          this.message = \"level-4-sign-1\";
+        }
+      }
+    ");
+    let mut state = level_state(4);
+    state.flags.insert("careful");
+    
+    run_script(&scripts, &mut state, &Bump);
+    assert_eq!(state.script_variables.get("bumps"), Some(&1));
+    assert_eq!(state.script_variables.get("level"), Some(&4));
+    assert_eq!(state.script_variables.get("careful"), Some(&1));
+    assert_eq!(state.script_variables.get("spinies"), Some(&6));
+    assert_eq!(state.temporary_walls.iter().map(|temporary_wall| temporary_wall.pos).collect::<Vec<Pos>>(), vec![[7, 2]]);
+    assert_eq!(state.message.map(message_key), Some("level-4-sign-1".to_string()));
+    
+    run_script(&scripts, &mut state, &Bump);
+    assert_eq!(state.script_variables.get("bumps"), Some(&2));
+    assert!(state.temporary_walls.is_empty());
+    
+    // only the callbacks which the script defines run
+    run_script(&scripts, &mut state, &Pause);
+    assert_eq!(state.script_variables.get("bumps"), Some(&2));
+  }
+  
+  #[test]
+  fn failed_callbacks_leave_the_room_unchanged() {
+    let scripts = scripts_for(4, "
+      fn on_bump() {
+        this.set(\"bumps\", 1);
+        this.add_wall(7, 2);
+        this.no_such_function();
+      }
+    ");
+    let mut state = level_state(4);
+    
+    run_script(&scripts, &mut state, &Bump);
+    assert!(state.script_variables.is_empty());
+    assert!(state.temporary_walls.is_empty());
+  }
+  
+  #[test]
+  fn spiny_changes_wait_until_the_spinies_are_aligned() {
+    let scripts = scripts_for(4, "
+      fn on_bump() {
+        this.add_spiny(7, 2, 0, 1);
+        this.remove_spiny(2, 4);
+      }
+    ");
+    let mut state = level_state(4);
+    let mut audio = NullAudio;
+    
+    // halfway through the move of the spiny from [2, 5] to [2, 4]
+    update(&mut state, &scripts, &mut audio, TimePasses(0.06));
+    run_script(&scripts, &mut state, &Bump);
+    assert_eq!(state.spinies.len(), 6);
+    assert!(!state.spinies.iter().any(|spiny| spiny.pos == [7, 2]));
+    
+    update(&mut state, &scripts, &mut audio, TimePasses(0.1));
+    assert_eq!(state.spinies_moving_since, state.time);
+    assert_eq!(state.spinies.len(), 6);
+    assert!(state.spinies.iter().any(|spiny| spiny.pos == [7, 2] && spiny.dir == DOWN));
+    assert!(!state.spinies.iter().any(|spiny| spiny.pos == [2, 4]));
+    assert!(state.spiny_changes.is_empty());
+    
+    // once aligned, the changes are immediate
+    run_script(&scripts, &mut state, &Bump);
+    assert_eq!(state.spinies.len(), 7);
+  }
+  
+  #[test]
+  fn each_message_is_leaked_once() {
+    let first = intern_message("a script's text".to_string());
+    let second = intern_message("a script's text".to_string());
+    assert!(match (first, second) {
+      (Message::ScriptM(first), Message::ScriptM(second)) => first.as_ptr() == second.as_ptr(),
+      _                                                   => false,
+    });
+  }
+  
+  #[test]
+  fn broken_scripts_are_skipped() {
+    let directory = env::temp_dir().join(format!("isrt-scripts-{}", process::id()));
+    fs::create_dir_all(directory.join("scripts")).unwrap();
+    File::create(directory.join("scripts/level-1.rhai")).unwrap().write_all(b"fn on_bump() { this.set(\"x\", 1); }").unwrap();
+    File::create(directory.join("scripts/level-2.rhai")).unwrap().write_all(b"fn on_bump( {").unwrap();
+    
+    let (scripts, errors) = load_scripts_with(|relative_path| {
+      Some(directory.join(relative_path)).filter(|path| path.exists())
+    });
+    fs::remove_dir_all(&directory).unwrap();
+    
+    let mut levels: Vec<LevelNumber> = scripts.asts.keys().cloned().collect();
+    levels.sort();
+    assert_eq!(levels, vec![1]);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("level-2.rhai"));
+  }
+}
@@ -0,0 +1,498 @@
+use std::fs::File;
+use std::io::{ Read, Write };
+use bincode;
+use serde::de::IgnoredAny;
+use serde_json;
+
+use assist::*;
+use levels::*;
+use settings::*;
+use state::*;
+use types::*;
+use types::AnimatedPos::*;
+use types::Message::*;
+use types::SpinyChange::*;
+
+
+// A State can be saved as JSON, or as a more compact binary encoding of the same values. Both start with a version
+// number, which must be incremented whenever the fields of State or of the types it contains change, so that older
+// saves are rejected instead of being misread.
+//
+// The fields are written by serde, in the order in which they are declared. In JSON, a state looks like this, except
+// that serde_json writes one array element per line:
+//
+//   {
+//     "version": 5,
+//     "state": {
+//       "time": 12.5,
+//       "message": {"SignM": [7, 0]},          # or null, "TitleM", "PausedM", "TheEndM", "AssistsM", {"ScriptM": "..."}
+//       "message_page": 0,
+//       ...
+//       "players": [{..., "pos": {"MovingSince": [[3, 2], [0, 1], 12.4]}}],   # positions and directions are [x, y]
+//       ...
+//       "flags": ["confused-by-numbers"],      # sorted
+//       "script_variables": {"visits": 2}      # sorted
+//     }
+//   }
+//
+// The binary encoding starts with the bytes "ISRT", followed by the same versioned state encoded with bincode.
+pub const STATE_FORMAT_VERSION: i64 = 5;
+
+const BINARY_MAGIC: &'static [u8] = b"ISRT";
+
+#[derive(Serialize,Deserialize)]
+struct Versioned<S> {
+  version: i64,
+  state: S,
+}
+
+
+// The messages chosen by the scripts are interned, see intern_message, so they are saved as the text itself.
+#[derive(Serialize,Deserialize)]
+enum SavedMessage {
+  TitleM,
+  PausedM,
+  TheEndM,
+  AssistsM,
+  SignM(LevelNumber, u8),
+  ScriptM(String),
+}
+
+pub mod optional_message {
+  use serde::{ Deserialize, Deserializer, Serialize, Serializer };
+  use script::*;
+  use types::*;
+  use super::SavedMessage;
+  
+  pub fn serialize<S: Serializer>(message: &Option<Message>, serializer: S) -> Result<S::Ok, S::Error> {
+    let saved_message = message.map(|message| match message {
+      Message::TitleM                          => SavedMessage::TitleM,
+      Message::PausedM                         => SavedMessage::PausedM,
+      Message::TheEndM                         => SavedMessage::TheEndM,
+      Message::AssistsM                        => SavedMessage::AssistsM,
+      Message::SignM(level_number, sign_index) => SavedMessage::SignM(level_number, sign_index),
+      Message::ScriptM(key)                    => SavedMessage::ScriptM(key.to_string()),
+    });
+    saved_message.serialize(serializer)
+  }
+  
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Message>, D::Error> {
+    let saved_message = Option::<SavedMessage>::deserialize(deserializer)?;
+    Ok(saved_message.map(|saved_message| match saved_message {
+      SavedMessage::TitleM                          => Message::TitleM,
+      SavedMessage::PausedM                         => Message::PausedM,
+      SavedMessage::TheEndM                         => Message::TheEndM,
+      SavedMessage::AssistsM                        => Message::AssistsM,
+      SavedMessage::SignM(level_number, sign_index) => Message::SignM(level_number, sign_index),
+      SavedMessage::ScriptM(key)                    => intern_message(key),
+    }))
+  }
+}
+
+// Flags are saved by name, sorted so that equal states are saved as the same bytes.
+pub mod flag_set {
+  use std::collections::HashSet;
+  use serde::{ Deserialize, Deserializer, Serialize, Serializer };
+  use serde::de::Error;
+  use levels::*;
+  use types::*;
+  
+  pub fn serialize<S: Serializer>(flags: &HashSet<Flag>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut flags: Vec<Flag> = flags.iter().cloned().collect();
+    flags.sort();
+    flags.serialize(serializer)
+  }
+  
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashSet<Flag>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?.iter().map(|name| {
+      find_flag(name).ok_or_else(|| D::Error::custom(format!("no sign offers the choice {:?}", name)))
+    }).collect()
+  }
+}
+
+
+fn invalid<A>(what: &str) -> Result<A, String> {
+  Err(format!("invalid state: {}", what))
+}
+
+// Levels past the last one are allowed, they are where the player goes after the last level.
+fn check_level(level_number: LevelNumber) -> Result<(), String> {
+  if level_number > max_level() + 1 {
+    return invalid(&format!("there is no level {}", level_number));
+  }
+  
+  Ok(())
+}
+
+fn check_dir(dir: Dir) -> Result<(), String> {
+  if ![UP, LEFT, DOWN, RIGHT].contains(&dir) {
+    return invalid(&format!("expected up, left, down or right, got {:?}", dir));
+  }
+  
+  Ok(())
+}
+
+// The spinies take 1/speed seconds to move one cell.
+fn check_speed(speed: f64) -> Result<(), String> {
+  if !(speed > 0.0 && speed.is_finite()) {
+    return invalid(&format!("expected a positive speed, got {}", speed));
+  }
+  
+  Ok(())
+}
+
+// The game indexes the sign's pages and choices with these, so they must fit the message.
+fn check_message(state: &State) -> Result<(), String> {
+  if let Some(SignM(level_number, sign_index)) = state.message {
+    if level_number < min_level() || level_number > max_level() {
+      return invalid(&format!("there is no level {}", level_number));
+    }
+    if !has_sign(level_number, sign_index) {
+      return invalid(&format!("there is no sign {} in level {}", sign_index, level_number));
+    }
+  }
+  
+  let (page_count, choice_count) = match state.message {
+    None           => (1, 1),
+    Some(AssistsM) => (1, ASSIST_CHOICE_COUNT as usize),
+    Some(message)  => {
+      let sign = sign_description(message, &state.flags);
+      (sign.pages, sign.choices.len().max(1))
+    },
+  };
+  
+  if state.message_page >= page_count {
+    return invalid(&format!("the message has no page {}", state.message_page));
+  }
+  if state.selected_choice as usize >= choice_count {
+    return invalid(&format!("the message has no choice {}", state.selected_choice));
+  }
+  
+  Ok(())
+}
+
+// serde only checks the types, these are the values which the game can't cope with.
+fn check_state(state: &State) -> Result<(), String> {
+  if state.level_number < min_level() || state.level_number > max_level() {
+    return invalid(&format!("there is no level {}", state.level_number));
+  }
+  check_level(state.previous_level)?;
+  if let Some(level_number) = state.next_level {
+    check_level(level_number)?;
+  }
+  
+  if state.players.is_empty() {
+    return invalid("there must be at least one player");
+  }
+  for player in &state.players {
+    for &dir in player.most_recent_dir.iter().chain(&player.buffered_dir) {
+      check_dir(dir)?;
+    }
+    match player.pos {
+      Idle(_)                                                                        => {},
+      MovingSince(_, dir, _)                                                         => check_dir(dir)?,
+      MovingOutSince(level_src, level_dst, _) | MovingInUntil(level_src, level_dst, _) => {
+        check_level(level_src)?;
+        check_level(level_dst)?;
+      },
+    }
+  }
+  
+  for spiny in &state.spinies {
+    check_dir(spiny.dir)?;
+  }
+  for spiny_change in &state.spiny_changes {
+    if let AddSpinyC(ref spiny) = *spiny_change {
+      check_dir(spiny.dir)?;
+    }
+  }
+  check_speed(state.spinies_speed)?;
+  check_speed(state.assists.spiny_speed_factor)?;
+  
+  check_message(state)
+}
+
+fn check_version(version: i64) -> Result<(), String> {
+  if version == STATE_FORMAT_VERSION {
+    Ok(())
+  } else {
+    Err(format!("unsupported state version {}, expected version {}", version, STATE_FORMAT_VERSION))
+  }
+}
+
+fn json_error(err: serde_json::Error) -> String {
+  if err.is_data() {
+    format!("invalid state: {}", err)
+  } else {
+    format!("syntax error in state: {}", err)
+  }
+}
+
+
+pub fn state_to_json(state: &State) -> String {
+  let mut json = serde_json::to_string_pretty(&Versioned { version: STATE_FORMAT_VERSION, state: state }).unwrap();
+  json.push('\n');
+  
+  json
+}
+
+// The version is checked first, so that an older save is reported as such rather than as a missing field.
+pub fn state_from_json(text: &str) -> Result<State, String> {
+  let versioned: Versioned<IgnoredAny> = serde_json::from_str(text).map_err(json_error)?;
+  check_version(versioned.version)?;
+  
+  let versioned: Versioned<State> = serde_json::from_str(text).map_err(json_error)?;
+  check_state(&versioned.state)?;
+  
+  Ok(versioned.state)
+}
+
+pub fn state_to_binary(state: &State) -> Vec<u8> {
+  let mut bytes = BINARY_MAGIC.to_vec();
+  bincode::serialize_into(&mut bytes, &Versioned { version: STATE_FORMAT_VERSION, state: state }).unwrap();
+  
+  bytes
+}
+
+// The binary state without the fields which don't affect the gameplay, to check that two games played in lock-step
+// are still the same game. Those are the display settings, which each player chooses for themselves, and the
+// particles and screen shake, which use sin and cos and so may differ slightly from one machine to the next. Since
+// the check ignores them, they must never influence the gameplay, otherwise two games could diverge unnoticed.
+pub fn gameplay_to_binary(state: &State) -> Vec<u8> {
+  let mut gameplay = state.clone();
+  gameplay.particles.clear();
+  gameplay.shake_t0 = None;
+  gameplay.memory_overlay = false;
+  gameplay.inspector_overlay = false;
+  gameplay.effects = false;
+  
+  state_to_binary(&gameplay)
+}
+
+pub fn state_from_binary(bytes: &[u8]) -> Result<State, String> {
+  if !bytes.starts_with(BINARY_MAGIC) {
+    return Err("syntax error in state: this is not a binary state".to_string());
+  }
+  
+  let mut reader = &bytes[BINARY_MAGIC.len()..];
+  let version: i64 = bincode::deserialize_from(&mut reader).map_err(|err| format!("invalid state: {}", err))?;
+  check_version(version)?;
+  
+  let state: State = bincode::deserialize_from(&mut reader).map_err(|err| format!("invalid state: {}", err))?;
+  if !reader.is_empty() {
+    return invalid(&format!("{} unexpected bytes after the state", reader.len()));
+  }
+  check_state(&state)?;
+  
+  Ok(state)
+}
+
+// Files whose name ends with ".bin" use the binary encoding, the others use JSON.
+pub fn save_state(state: &State, path: &str) -> Result<(), String> {
+  let bytes = if path.ends_with(".bin") { state_to_binary(state) } else { state_to_json(state).into_bytes() };
+  File::create(path).and_then(|mut file| file.write_all(&bytes))
+                    .map_err(|err| format!("could not write {}: {}", path, err))
+}
+
+pub fn load_state(path: &str) -> Result<State, String> {
+  let mut bytes = Vec::new();
+  File::open(path).and_then(|mut file| file.read_to_end(&mut bytes))
+                  .map_err(|err| format!("could not read {}: {}", path, err))?;
+  
+  if path.ends_with(".bin") {
+    state_from_binary(&bytes)
+  } else {
+    String::from_utf8(bytes).map_err(|_| format!("could not read {}: not UTF-8", path))
+                            .and_then(|text| state_from_json(&text))
+  }
+}
+
+// "--load FILE" starts the game from a state saved with F4.
+pub fn load_setting() -> Option<String> {
+  command_line_option("load")
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use scenario::*;
+  use script::*;
+  
+  
+  // A state in which every field has a value other than its initial one.
+  fn eventful_state() -> State {
+    load_test_level(7, &SIGNS_ROOM.replacen("sign 0", "sign 0 pages 2", 1));
+    let mut state = run_scenario("
+      level 4
+      press right x4
+      wait 0.3
+      expect deaths 1
+    ").state;
+    
+    state.time += 0.1 + 0.2; // not exactly 0.3
+    state.message = Some(SignM(7, 0));
+    state.message_page = 1;
+    state.selected_choice = 1;
+    state.next_level = Some(5);
+    state.players[0].pos = MovingSince([3, 2], DOWN, state.time - 1.0 / 3.0);
+    state.players[0].most_recent_dir = Some(LEFT);
+    state.players[0].buffered_dir = Some(UP);
+    state.players[0].down_pressed = true;
+    set_player_count(&mut state, 2);
+    state.players[1].pos = MovingOutSince(4, 5, state.time);
+    state.spinies[0].enabled = false;
+    state.spiny_changes = vec![AddSpinyC(state.spinies[1].clone()), RemoveSpinyC([4, 4])];
+    state.memory_overlay = true;
+    state.inspector_overlay = true;
+    state.effects = false;
+    state.assists = Assists { spiny_speed_factor: 0.75, hitbox_margin: 0.25, invulnerable: true };
+    state.spinies_speed = 6.0;
+    state.flags.insert(find_flag("careful").unwrap());
+    state.script_variables.insert("visits \"quoted\"".to_string(), -1 << 40);
+    
+    state
+  }
+  
+  fn check_json_round_trip(state: &State) {
+    let json = state_to_json(state);
+    let loaded = state_from_json(&json).unwrap_or_else(|err| panic!("{}\n{}", err, json));
+    assert_eq!(state_to_json(&loaded), json);
+  }
+  
+  fn check_binary_round_trip(state: &State) {
+    let bytes = state_to_binary(state);
+    let loaded = state_from_binary(&bytes).unwrap();
+    assert_eq!(state_to_binary(&loaded), bytes);
+    assert_eq!(state_to_json(&loaded), state_to_json(state));
+  }
+  
+  
+  #[test]
+  fn initial_states_round_trip() {
+    check_json_round_trip(&initial_state());
+    check_binary_round_trip(&initial_state());
+    for level_number in min_level()..max_level()+1 {
+      check_json_round_trip(&level_state(level_number));
+      check_binary_round_trip(&level_state(level_number));
+    }
+  }
+  
+  #[test]
+  fn eventful_states_round_trip() {
+    let state = eventful_state();
+    assert!(!state.corpses.is_empty());
+    assert!(!state.particles.is_empty());
+    assert!(state.shake_t0.is_some());
+    
+    check_json_round_trip(&state);
+    check_binary_round_trip(&state);
+    
+    let loaded = state_from_json(&state_to_json(&state)).unwrap();
+    assert_eq!(loaded.time, state.time);
+    assert_eq!(loaded.message, Some(SignM(7, 0)));
+    assert!(loaded.flags.contains("careful"));
+    assert_eq!(loaded.assists, state.assists);
+    assert_eq!(loaded.players.len(), 2);
+    assert_eq!(loaded.script_variables.get("visits \"quoted\""), Some(&(-1 << 40)));
+  }
+  
+  #[test]
+  fn every_message_and_position_round_trips() {
+    let messages = [TitleM, PausedM, TheEndM, AssistsM, SignM(1, 0), intern_message("a script's text\nwith ünicode 🦔".to_string())];
+    for &message in &messages {
+      let mut state = level_state(min_level());
+      state.message = Some(message);
+      let loaded = state_from_json(&state_to_json(&state)).unwrap();
+      assert_eq!(loaded.message, Some(message));
+      check_binary_round_trip(&state);
+    }
+    
+    let positions = vec![Idle([1, 2]), MovingSince([3, 4], LEFT, 1e-9), MovingOutSince(3, 4, 12.25), MovingInUntil(5, 4, 1e20)];
+    for pos in positions {
+      let mut state = level_state(min_level());
+      state.players[0].pos = pos;
+      check_json_round_trip(&state);
+      check_binary_round_trip(&state);
+    }
+  }
+  
+  #[test]
+  fn the_json_format_is_documented() {
+    let json = state_to_json(&eventful_state());
+    assert!(json.starts_with("{\n  \"version\": 5,\n  \"state\": {\n    \"time\": "));
+    
+    // serde_json writes one array element per line, so compare the compact form.
+    let compact = serde_json::to_string(&serde_json::from_str::<serde_json::Value>(&json).unwrap()).unwrap();
+    assert!(compact.contains("\"message\":{\"SignM\":[7,0]}"));
+    assert!(compact.contains("\"pos\":{\"MovingOutSince\":[4,5,"));
+    assert!(compact.contains("\"flags\":[\"careful\"]"));
+    assert!(compact.contains("\"script_variables\":{\"visits \\\"quoted\\\"\":-1099511627776}"));
+  }
+  
+  #[test]
+  fn the_gameplay_excludes_the_cosmetic_fields() {
+    let state = eventful_state();
+    let mut other = state.clone();
+    other.particles.clear();
+    other.shake_t0 = None;
+    other.memory_overlay = !state.memory_overlay;
+    other.inspector_overlay = !state.inspector_overlay;
+    other.effects = !state.effects;
+    assert_eq!(gameplay_to_binary(&other), gameplay_to_binary(&state));
+    
+    other.corpses.clear();
+    assert!(gameplay_to_binary(&other) != gameplay_to_binary(&state));
+  }
+  
+  #[test]
+  fn other_versions_are_rejected() {
+    let json = state_to_json(&initial_state()).replacen("\"version\": 5", "\"version\": 4", 1);
+    assert_eq!(state_from_json(&json).err().unwrap(), "unsupported state version 4, expected version 5");
+    
+    let mut bytes = state_to_binary(&initial_state());
+    bytes[BINARY_MAGIC.len()] = 6; // little-endian
+    assert_eq!(state_from_binary(&bytes).err().unwrap(), "unsupported state version 6, expected version 5");
+  }
+  
+  #[test]
+  fn invalid_states_are_rejected() {
+    let json = state_to_json(&eventful_state());
+    for &(from, to) in &[("\"MovingSince\"", "\"Teleporting\""), ("\"careful\"", "\"no-such-flag\""), ("\"level_number\": 4", "\"level_number\": 99"), ("\"spinies_speed\": 6.0", "\"spinies_speed\": 0.0"), ("-1,", "-2,")] {
+      assert!(state_from_json(&json.replacen(from, to, 1)).is_err(), "{} -> {}", from, to);
+    }
+    assert!(state_from_json(&json[..json.len() / 2]).is_err());
+    assert!(state_from_json(&format!("{} {{}}", json)).is_err());
+    
+    let bytes = state_to_binary(&eventful_state());
+    for length in 0..bytes.len() {
+      assert!(state_from_binary(&bytes[..length]).is_err());
+    }
+    assert!(state_from_binary(&[bytes.clone(), vec![0]].concat()).is_err());
+  }
+  
+  #[test]
+  fn messages_must_fit_their_sign() {
+    let no_level_after_the_last = format!("there is no level {}", max_level() + 1);
+    let cases = vec![
+      (None,                             1, 0, "the message has no page 1"),
+      (None,                             0, 1, "the message has no choice 1"),
+      (Some(AssistsM),                   0, ASSIST_CHOICE_COUNT, "the message has no choice 4"),
+      (Some(SignM(0, 0)),                0, 0, "there is no level 0"),
+      (Some(SignM(max_level() + 1, 0)),  0, 0, &no_level_after_the_last[..]),
+      (Some(SignM(7, 2)),                0, 0, "there is no sign 2 in level 7"),
+      (Some(SignM(7, 0)),                2, 0, "the message has no page 2"),
+      (Some(SignM(7, 0)),                1, 2, "the message has no choice 2"),
+      (Some(SignM(7, 0)),                1, 255, "the message has no choice 255"),
+    ];
+    for (message, message_page, selected_choice, error) in cases {
+      let mut state = eventful_state();
+      state.message = message;
+      state.message_page = message_page;
+      state.selected_choice = selected_choice;
+      let error = format!("invalid state: {}", error);
+      assert_eq!(state_from_json(&state_to_json(&state)).err(), Some(error.clone()));
+      assert_eq!(state_from_binary(&state_to_binary(&state)).err(), Some(error));
+    }
+  }
+}
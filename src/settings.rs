@@ -0,0 +1,29 @@
+use std::env;
+
+
+// "--name value" or "--name=value"
+pub fn command_line_option(name: &str) -> Option<String> {
+  let flag = format!("--{}", name);
+  let prefix = format!("--{}=", name);
+  
+  let args: Vec<String> = env::args().collect();
+  for (i, arg) in args.iter().enumerate() {
+    if arg.starts_with(&prefix) {
+      return Some(arg[prefix.len()..].to_string());
+    }
+    if *arg == flag {
+      if let Some(value) = args.get(i+1) {
+        return Some(value.clone());
+      }
+    }
+  }
+  
+  None
+}
+
+// "--name"
+pub fn command_line_flag(name: &str) -> bool {
+  let flag = format!("--{}", name);
+  
+  env::args().any(|arg| arg == flag)
+}
@@ -6,32 +6,46 @@ use levels::*;
 use types::*;
 
 
-pub fn compute_spiny_f_pos(spiny: &MovingSpiny, t0: Seconds, t: Seconds) -> FPos {
+// `speed` is in cells per second, normally SPINY_SPEED but the assists can make it slower.
+pub fn compute_spiny_f_pos(spiny: &MovingSpiny, speed: f64, t0: Seconds, t: Seconds) -> FPos {
   if spiny.enabled {
-    compute_f_pos(spiny.pos, spiny.dir, SPINY_SPEED, t0, t)
+    compute_f_pos(spiny.pos, spiny.dir, speed, t0, t)
   } else {
     [spiny.pos[0] as f64, spiny.pos[1] as f64]
   }
 }
 
 
+fn is_obstacle(level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, pos: Pos) -> bool {
+  use levels::Cell::*;
+  
+  match current_cell_at(level_number, temporary_walls, pos) {
+    LeftDoorC | RightDoorC | SignC(_) | SpawnerC(_) | WallC => true,
+    _                                                       => false,
+  }
+}
+
+// Spinies move in unison, that is, they are cell-aligned on the same frame and they are the same fraction of the way
+// to the next cell as every other spiny. This simplifies collisions: they can only happen at two moments of each move.
+#[derive(Clone,Copy,PartialEq)]
+enum Moment {
+  CellBoundary, // at t0, when the spinies are aligned with the grid
+  CellMiddle,   // halfway through the move, at t0 + 0.5 / speed
+}
+
 fn should_bounce(
   src: Pos,
   dir: Dir,
   spinies_src: &HashMap<Pos, Dir>, // all the spinies, accessible by src.
   spinies_dst: &HashMap<Pos, i8>,  // all the spinies, accessible by dst.
   level_number: LevelNumber,
-  t0: Seconds,
-  t: Seconds
+  temporary_walls: &Vec<TemporaryWall>,
+  moment: Moment
 ) -> bool {
-  use levels::Cell::*;
+  use self::Moment::*;
   
   let dst = add(src, dir);
   
-  // Spinies move in unison, that is, they are cell-aligned on the same frame and they are the same
-  // fraction of the way to the next cell as every other spiny. This simplifies collisions.
-  let dt = t - t0;
-  
   // Case 0: collision with a wall
   //   .................  .........................
   //   .       .       .  .       .       .       .
@@ -41,13 +55,10 @@ fn should_bounce(
   //   .       .       .  .       .       .       .
   //   .................  .........................
   // 
-  // We bounce at SPINY_HALF_MOVE_DURATION, not t0, in order to avoid a corner case when a spiny is stuck between
+  // We bounce halfway through the move, not at t0, in order to avoid a corner case when a spiny is stuck between
   // two walls.
-  if dt > SPINY_HALF_MOVE_DURATION {
-    match cell_at(level_number, dst) {
-      LeftDoorC | RightDoorC | SignC(_) | WallC => return true,
-      _                                         => {},
-    }
+  if moment == CellMiddle && is_obstacle(level_number, temporary_walls, dst) {
+    return true;
   }
   
   // Case 1: collision with a spiny at a cell boundary
@@ -61,9 +72,11 @@ fn should_bounce(
   //   .................  .................  .................           .................
   // 
   // This can only happen at t0, otherwise we would have bounced already.
-  if let Some(dir2) = spinies_src.get(&dst) {
-    if dir != *dir2 {
-      return true
+  if moment == CellBoundary {
+    if let Some(dir2) = spinies_src.get(&dst) {
+      if dir != *dir2 {
+        return true
+      }
     }
   }
   
@@ -83,8 +96,8 @@ fn should_bounce(
   //                                      .       .                                              .       .
   //                                      .........                                              .........
   // 
-  // Already at t0 the collision is inevitable, but only the collision only happens after SPINY_HALF_MOVE_DURATION.
-  if dt > SPINY_HALF_MOVE_DURATION {
+  // Already at t0 the collision is inevitable, but only the collision only happens halfway through the move.
+  if moment == CellMiddle {
     if let Some(nb_overlapping_spinies) = spinies_dst.get(&dst) {
       if *nb_overlapping_spinies > 1 {
         return true
@@ -112,7 +125,7 @@ fn should_bounce(
   // collision with it in the next frame and it will bounce back towards the obstacle.
   if let Some(dir2) = spinies_src.get(&dst) {
     if dir == *dir2 {
-      if should_bounce(dst, *dir2, spinies_src, spinies_dst, level_number, t0, t) {
+      if should_bounce(dst, *dir2, spinies_src, spinies_dst, level_number, temporary_walls, moment) {
         return true;
       }
     }
@@ -121,42 +134,87 @@ fn should_bounce(
   false
 }
 
-fn bounce_spiny(spiny: &mut MovingSpiny, t0: Seconds, t: Seconds) {
-  let dt = t - t0;
+fn bounce_spiny(spiny: &mut MovingSpiny, moment: Moment) {
+  use self::Moment::*;
   
-  spiny.pos = if dt <= SPINY_HALF_MOVE_DURATION {
-                // Case 1: collision at a cell boundary
-                spiny.pos
-              } else {
-                // Case 2: collision in the middle of a cell
-                add(spiny.pos, spiny.dir)
+  spiny.pos = match moment {
+                CellBoundary => spiny.pos,
+                CellMiddle   => add(spiny.pos, spiny.dir),
               };
   spiny.dir = mul_scalar(spiny.dir, -1);
 }
 
-pub fn update_spinies(spinies: &mut Vec<MovingSpiny>, level_number: LevelNumber, t0: &mut Seconds, t: Seconds) {
-  if t >= *t0 + SPINY_MOVE_DURATION {
-    *t0 = t;
-    
-    // Keep moving in the same direction, we'll handle collisions in a moment.
-    for spiny in spinies.iter_mut() {
-      if spiny.enabled {
-        spiny.pos = add(spiny.pos, spiny.dir);
-      }
+// Temporarily disable some spinies if they occupy the same cell, and re-enable them once the cell is free.
+pub fn disable_overlapping_spinies(spinies: &mut Vec<MovingSpiny>) {
+  let mut occupied = HashSet::new();
+  for spiny in spinies.iter_mut() {
+    if occupied.contains(&spiny.pos) {
+      spiny.enabled = false;
+    } else {
+      occupied.insert(spiny.pos);
+      spiny.enabled = true;
     }
-    
-    // re-enable spinies if appropriate
-    let mut occupied = HashSet::new();
-    for spiny in spinies.iter_mut() {
-      if occupied.contains(&spiny.pos) {
-        spiny.enabled = false;
-      } else {
-        occupied.insert(&spiny.pos);
-        spiny.enabled = true;
-      }
+  }
+}
+
+// Must only be called when the spinies are aligned with the grid.
+pub fn apply_spiny_changes(spinies: &mut Vec<MovingSpiny>, spiny_changes: Vec<SpinyChange>) {
+  use types::SpinyChange::*;
+  
+  if spiny_changes.is_empty() {
+    return;
+  }
+  
+  for spiny_change in spiny_changes {
+    match spiny_change {
+      AddSpinyC(spiny)  => spinies.push(spiny),
+      RemoveSpinyC(pos) => spinies.retain(|spiny| spiny.pos != pos),
     }
   }
   
+  // the script may have added a spiny on top of another one
+  disable_overlapping_spinies(spinies);
+}
+
+// Sinks swallow the spinies which enter them.
+fn sink_spinies(spinies: &mut Vec<MovingSpiny>, level_number: LevelNumber) {
+  use levels::Cell::*;
+  
+  spinies.retain(|spiny| cell_at(level_number, spiny.pos) != SinkC);
+}
+
+// Spawners emit a new spiny in front of them every few steps, unless a spiny or an obstacle is already there.
+fn spawn_spinies(spinies: &mut Vec<MovingSpiny>, level_number: LevelNumber, temporary_walls: &Vec<TemporaryWall>, step: u32) {
+  use levels::Cell::*;
+  
+  for j in 0..LEVEL_HEIGHT {
+    for i in 0..LEVEL_WIDTH {
+      if let SpawnerC(spawner) = cell_at(level_number, [i,j]) {
+        let dst = add([i,j], spawner.dir);
+        
+        let free = !is_obstacle(level_number, temporary_walls, dst) && !spinies.iter().any(|spiny| spiny.pos == dst);
+        if step % spawner.period as u32 == 0 && free {
+          spinies.push(
+            MovingSpiny {
+              pos: dst,
+              dir: spawner.dir,
+              lifetime: lifetime_at(level_number, [i,j]), // the spiny lasts as long as its spawner
+              enabled: true,
+            }
+          );
+        }
+      }
+    }
+  }
+}
+
+fn bounce_spinies(
+  spinies: &mut Vec<MovingSpiny>,
+  level_number: LevelNumber,
+  temporary_walls: &Vec<TemporaryWall>,
+  moment: Moment,
+  bounces: &mut Vec<FPos>
+) {
   // Our spinies are on a grid, so we should be able to look up spinies by their position.
   let mut spinies_src = HashMap::with_capacity(spinies.len());
   for spiny in spinies.iter() {
@@ -166,19 +224,441 @@ pub fn update_spinies(spinies: &mut Vec<MovingSpiny>, level_number: LevelNumber,
   }
   
   // But the spinies are also moving, so their position overlaps two cells: their source and destination positions.
-  // If two spinies are about to collide head-on in the middle of a cell, they can have the same destination.
+  // If two spinies are about to collide head-on in the middle of a cell, they can have the same destination. A spiny
+  // which is about to bounce on an obstacle will end up back in its source position, so that's its destination.
   let mut spinies_dst = HashMap::with_capacity(spinies.len());
   for spiny in spinies.iter() {
     if spiny.enabled {
       let dst = add(spiny.pos, spiny.dir);
+      let dst = if is_obstacle(level_number, temporary_walls, dst) { spiny.pos } else { dst };
       *spinies_dst.entry(dst).or_insert(0) += 1;
     }
   }
   
   // We now have everything we need to determine if a spiny should bounce.
   for spiny in spinies.iter_mut() {
-    if spiny.enabled && should_bounce(spiny.pos, spiny.dir, &mut spinies_src, &mut spinies_dst, level_number, *t0, t) {
-      bounce_spiny(spiny, *t0, t);
+    if spiny.enabled && should_bounce(spiny.pos, spiny.dir, &spinies_src, &spinies_dst, level_number, temporary_walls, moment) {
+      // the middle of the spiny's leading edge, which is half a cell further at CellMiddle
+      let edge = if moment == Moment::CellBoundary { 0.5 } else { 1.0 };
+      bounces.push([spiny.pos[0] as f64 + 0.5 + edge * spiny.dir[0] as f64, spiny.pos[1] as f64 + 0.5 + edge * spiny.dir[1] as f64]);
+      
+      bounce_spiny(spiny, moment);
+    }
+  }
+}
+
+// Runs the collision checks of the moments of the move which started at t0 and which the frame reaches, once.
+// Checking again on the next frame would see the spinies which have already bounced and make them bounce back.
+#[allow(clippy::too_many_arguments)]
+fn check_moments(
+  spinies: &mut Vec<MovingSpiny>,
+  level_number: LevelNumber,
+  temporary_walls: &Vec<TemporaryWall>,
+  move_duration: Seconds,
+  t0: Seconds,
+  previous_t: Seconds,
+  t: Seconds,
+  bounces: &mut Vec<FPos>
+) {
+  use self::Moment::*;
+  
+  for &(moment, moment_t) in &[(CellBoundary, t0), (CellMiddle, t0 + move_duration / 2.0)] {
+    if previous_t <= moment_t && moment_t < t {
+      bounce_spinies(spinies, level_number, temporary_walls, moment, bounces);
+    }
+  }
+}
+
+// Returns the positions at which spinies have bounced, if any. The speed may only change right after a call which
+// has aligned the spinies with the grid, that is, which has set t0 to t: the moments of the current move would
+// otherwise move, and one of them could be evaluated twice or not at all.
+#[allow(clippy::too_many_arguments)]
+pub fn update_spinies(
+  spinies: &mut Vec<MovingSpiny>,
+  level_number: LevelNumber,
+  temporary_walls: &Vec<TemporaryWall>,
+  speed: f64,
+  t0: &mut Seconds,
+  step: &mut u32,
+  previous_t: Seconds,
+  t: Seconds
+) -> Vec<FPos> {
+  let move_duration = 1.0 / speed;
+  let mut bounces = Vec::new();
+  let mut previous_t = previous_t;
+  
+  // A long frame, e.g. on a slow machine, can reach the end of the move or even of several moves, so we finish them
+  // one at a time, each with the moments it has left, before moving on to the next one.
+  while t >= *t0 + move_duration {
+    check_moments(spinies, level_number, temporary_walls, move_duration, *t0, previous_t, t, &mut bounces);
+    
+    // The last move starts on this frame, the ones it skipped over started when the previous one ended.
+    let move_end = *t0 + move_duration;
+    *t0 = if t >= move_end + move_duration { move_end } else { t };
+    previous_t = *t0;
+    *step += 1;
+    
+    // Keep moving in the same direction, we'll handle collisions in a moment.
+    for spiny in spinies.iter_mut() {
+      if spiny.enabled {
+        spiny.pos = add(spiny.pos, spiny.dir);
+      }
+    }
+    
+    // All the spinies are aligned with the grid, so this is the only time at which they can appear or disappear
+    // without breaking the lock-step assumptions of should_bounce.
+    sink_spinies(spinies, level_number);
+    spawn_spinies(spinies, level_number, temporary_walls, *step);
+    
+    // re-enable spinies if appropriate
+    disable_overlapping_spinies(spinies);
+  }
+  
+  check_moments(spinies, level_number, temporary_walls, move_duration, *t0, previous_t, t, &mut bounces);
+  
+  bounces
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  
+  // at the normal speed
+  const SPINY_MOVE_DURATION:      Seconds = 1.0 / SPINY_SPEED;
+  const SPINY_HALF_MOVE_DURATION: Seconds = SPINY_MOVE_DURATION / 2.0;
+  
+  // The rooms are loaded over the first level, using the same mechanism as the level files in dev mode.
+  fn test_level() -> LevelNumber {
+    min_level()
+  }
+  
+  fn load_room(map: &str) -> (Vec<MovingSpiny>, Vec<TemporaryWall>) {
+    let level_description = parse_level_description(map).unwrap();
+    set_level_override(test_level(), Some(Box::leak(Box::new(level_description))));
+    
+    (adjust_spinies(Vec::new(), test_level() - 1, test_level()),
+     adjust_walls(Vec::new(), test_level() - 1, test_level()))
+  }
+  
+  // The map uses the same syntax as the levels, but only the lines of the room, not the line of dots above it.
+  fn room(lines: &[&str]) -> String {
+    let mut map = " . . . . . . . . . .\n".to_string();
+    for line in lines {
+      map.push_str(line);
+      map.push('\n');
+    }
+    
+    map
+  }
+  
+  // Runs update_spinies at the given times and returns the spinies afterwards.
+  fn simulate(map: &str, times: &[Seconds]) -> Vec<MovingSpiny> {
+    let (mut spinies, temporary_walls) = load_room(map);
+    let mut t0 = 0.0;
+    let mut step = 0;
+    let mut previous_t = 0.0;
+    for &t in times {
+      update_spinies(&mut spinies, test_level(), &temporary_walls, SPINY_SPEED, &mut t0, &mut step, previous_t, t);
+      previous_t = t;
+    }
+    
+    spinies
+  }
+  
+  
+  // Case 0
+  #[test]
+  fn spinies_bounce_on_walls_halfway_through_the_cell() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  >>##        ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]);
+    
+    let before = simulate(&map, &[SPINY_HALF_MOVE_DURATION * 0.9]);
+    assert_eq!(before[0].dir, RIGHT);
+    
+    let after = simulate(&map, &[SPINY_HALF_MOVE_DURATION * 1.1]);
+    assert_eq!((after[0].pos, after[0].dir), ([3, 3], LEFT));
+  }
+  
+  // The frame which reaches the end of the move also checks the middle of the move, however long it is.
+  #[test]
+  fn spinies_bounce_on_walls_during_long_frames() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  >>##        ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]);
+    
+    let spinies = simulate(&map, &[SPINY_MOVE_DURATION * 1.2]);
+    assert_eq!((spinies[0].pos, spinies[0].dir), ([2, 3], LEFT));
+    
+    let spinies = simulate(&map, &[SPINY_MOVE_DURATION * 2.5]);
+    assert_eq!((spinies[0].pos, spinies[0].dir), ([1, 3], LEFT));
+  }
+  
+  // Case 1
+  #[test]
+  fn facing_spinies_bounce_at_the_cell_boundary() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  >><<        ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]);
+    
+    let spinies = simulate(&map, &[0.01]);
+    assert_eq!((spinies[0].pos, spinies[0].dir), ([2, 3], LEFT));
+    assert_eq!((spinies[1].pos, spinies[1].dir), ([3, 3], RIGHT));
+  }
+  
+  // Case 2
+  #[test]
+  fn spinies_heading_to_the_same_cell_bounce_in_the_middle_of_it() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  >>  <<      ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]);
+    
+    let spinies = simulate(&map, &[SPINY_HALF_MOVE_DURATION * 1.1]);
+    assert_eq!((spinies[0].pos, spinies[0].dir), ([3, 3], LEFT));
+    assert_eq!((spinies[1].pos, spinies[1].dir), ([3, 3], RIGHT));
+    
+    // back where they started once they are aligned with the grid again
+    let spinies = simulate(&map, &[SPINY_HALF_MOVE_DURATION * 1.1, SPINY_MOVE_DURATION]);
+    assert_eq!((spinies[0].pos, spinies[0].dir), ([2, 3], LEFT));
+    assert_eq!((spinies[1].pos, spinies[1].dir), ([4, 3], RIGHT));
+  }
+  
+  // Case 3
+  #[test]
+  fn a_spiny_following_a_bouncing_spiny_bounces_too() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  >>>>##      ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]);
+    
+    let spinies = simulate(&map, &[SPINY_HALF_MOVE_DURATION * 1.1]);
+    assert_eq!(spinies[0].dir, LEFT);
+    assert_eq!(spinies[1].dir, LEFT);
+  }
+  
+  
+  fn move_times(move_count: u32) -> Vec<Seconds> {
+    (1..move_count + 1).map(|i| i as f64 * SPINY_MOVE_DURATION).collect()
+  }
+  
+  #[test]
+  fn spawners_emit_a_spiny_every_period() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  G0          ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]) + "spawner > 2\n";
+    
+    assert_eq!(simulate(&map, &move_times(1)).len(), 0);
+    
+    let spinies = simulate(&map, &move_times(2));
+    assert_eq!(spinies.len(), 1);
+    assert_eq!((spinies[0].pos, spinies[0].dir), ([3, 3], RIGHT));
+    
+    let spinies = simulate(&map, &move_times(4));
+    assert_eq!(spinies.iter().map(|spiny| spiny.pos).collect::<Vec<Pos>>(), vec![[5, 3], [3, 3]]);
+  }
+  
+  #[test]
+  fn spawners_facing_an_obstacle_emit_nothing() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  G0##        ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]) + "spawner > 1\n";
+    
+    assert_eq!(simulate(&map, &move_times(4)).len(), 0);
+  }
+  
+  #[test]
+  fn spawners_must_have_a_period() {
+    let result = parse_level_description(&(room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  G0          ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]) + "spawner > 0\n"));
+    
+    assert!(result.err().unwrap().contains("period must be at least 1"));
+  }
+  
+  #[test]
+  fn spawners_must_be_described() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  G0    G1    ##.",
+      ".##              ##.",
+      ".##              RD.",
+      ".##################.",
+    ]) + "spawner > 1\n";
+    let level_description = parse_level_description(&map).unwrap();
+    set_level_override(test_level(), Some(Box::leak(Box::new(level_description))));
+    
+    assert!(check_level(test_level()).err().unwrap().contains("there is no spawner 1"));
+  }
+  
+  #[test]
+  fn sinks_swallow_the_spinies_entering_them() {
+    let map = room(&[
+      ".##################.",
+      ".LD            ####.",
+      ".##              ##.",
+      ".##  >>  OO      ##.",
+      ".##      OO    <<##.",
+      ".##              RD.",
+      ".##################.",
+    ]);
+    
+    assert_eq!(simulate(&map, &move_times(1)).len(), 2);
+    
+    let spinies = simulate(&map, &move_times(2));
+    assert_eq!(spinies.iter().map(|spiny| spiny.pos).collect::<Vec<Pos>>(), vec![[5, 4]]);
+    
+    assert_eq!(simulate(&map, &move_times(3)).len(), 0);
+  }
+  
+  
+  // xorshift64*, so that a failure can be reproduced from its seed
+  struct Rng {
+    state: u64,
+  }
+  
+  impl Rng {
+    fn new(seed: u64) -> Rng {
+      Rng { state: seed.wrapping_mul(0x9E3779B97F4A7C15) | 1 }
+    }
+    
+    fn below(&mut self, n: u64) -> u64 {
+      self.state ^= self.state >> 12;
+      self.state ^= self.state << 25;
+      self.state ^= self.state >> 27;
+      
+      (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 32) % n
+    }
+  }
+  
+  // 15% walls, 5% signs, 40% spinies and 40% floor
+  const RANDOM_CELLS: &'static [&'static str] = &[
+    "##", "##", "##", "S0",
+    "^^", "^^", "<<", "<<", "vv", "vv", ">>", ">>",
+    "  ", "  ", "  ", "  ", "  ", "  ", "  ", "  ",
+  ];
+  
+  // Walls and doors around the room, and random walls, signs and spinies inside it. No sinks nor spawners, so the
+  // number of spinies should never change.
+  fn random_room(rng: &mut Rng) -> String {
+    let mut lines = Vec::new();
+    for j in 0..LEVEL_HEIGHT {
+      let mut line = ".".to_string();
+      for i in 0..LEVEL_WIDTH {
+        let pos = [i, j];
+        let cell = if pos == LEFT_DOOR {
+                     "LD"
+                   } else if pos == RIGHT_DOOR {
+                     "RD"
+                   } else if !is_inside_room(pos) {
+                     "##"
+                   } else {
+                     RANDOM_CELLS[rng.below(RANDOM_CELLS.len() as u64) as usize]
+                   };
+        line.push_str(cell);
+      }
+      line.push('.');
+      lines.push(line);
+    }
+    
+    room(&lines.iter().map(|line| line.as_str()).collect::<Vec<&str>>())
+  }
+  
+  const ROOM_COUNT: u64 = 200;
+  const UPDATE_COUNT: usize = 1000;
+  
+  #[test]
+  fn spiny_invariants_hold_in_random_rooms() {
+    for seed in 0..ROOM_COUNT {
+      let mut rng = Rng::new(seed);
+      let map = random_room(&mut rng);
+      let (mut spinies, temporary_walls) = load_room(&map);
+      let spiny_count = spinies.len();
+      
+      let mut t0 = 0.0;
+      let mut step = 0;
+      let mut t = 0.0;
+      let mut speed = SPINY_SPEED;
+      for update in 0..UPDATE_COUNT {
+        // irregular frame durations, mostly short but sometimes longer than half a move or than a whole move, like
+        // the hitches of a slow machine
+        let previous_t = t;
+        t += match rng.below(20) {
+               0 => (0.5 + rng.below(50) as f64 / 100.0) * SPINY_MOVE_DURATION,
+               1 => (1.0 + rng.below(200) as f64 / 100.0) * SPINY_MOVE_DURATION,
+               _ => (1 + rng.below(4)) as f64 / 120.0,
+             };
+        update_spinies(&mut spinies, test_level(), &temporary_walls, speed, &mut t0, &mut step, previous_t, t);
+        let context = format!("seed {}, update {}, room:\n{}", seed, update, map);
+        
+        assert_eq!(spinies.len(), spiny_count, "the number of spinies changed, {}", context);
+        
+        // in between the grid-aligned ticks, the position is the cell the spiny is moving away from or towards
+        if t0 == t {
+          let mut occupied = HashSet::new();
+          for spiny in &spinies {
+            if spiny.enabled {
+              assert!(occupied.insert(spiny.pos), "two spinies at {:?}, {}", spiny.pos, context);
+            }
+            
+            let obstacle = is_obstacle(test_level(), &temporary_walls, spiny.pos);
+            assert!(!obstacle, "a spiny entered an obstacle at {:?}, {}", spiny.pos, context);
+          }
+          
+          // like the assists, which only change the speed when the spinies are aligned with the grid
+          speed = SPINY_SPEED / (1 + rng.below(2)) as f64;
+        }
+      }
     }
   }
 }
@@ -1,74 +1,105 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 
+use assist::*;
 use corpse::*;
 use levels::*;
+use particle::*;
 use player::*;
 use types::*;
+use types::Message::*;
 
 
+#[derive(Clone,Serialize,Deserialize)]
 pub struct State {
   pub time: Seconds,
   
+  #[serde(with = "::serialization::optional_message")]
   pub message: Option<Message>,
+  pub message_page: u8,
+  pub selected_choice: u8,
   pub frozen: bool,
   
   pub level_number: LevelNumber,
   pub previous_level: LevelNumber,
   pub next_level: Option<LevelNumber>,
   
-  pub player: Player,
+  pub players: Vec<Player>, // two in co-op mode
   pub corpses: VecDeque<Corpse>,
+  pub particles: Vec<Particle>,
+  pub shake_t0: Option<Seconds>,
   
   pub spinies_moving_since: Seconds,
+  pub spinies_speed: f64, // in cells per second, only changes when the spinies are aligned with the grid
+  pub spiny_steps: u32, // number of times the spinies have moved one cell, used to time the spawners
   pub spinies: Vec<MovingSpiny>,
+  pub spiny_changes: Vec<SpinyChange>, // made by the scripts, applied once the spinies are aligned with the grid
   
   pub temporary_walls: Vec<TemporaryWall>,
+  
+  pub memory_overlay: bool, // hint mode, showing the entities of the previous and next levels
+  pub inspector_overlay: bool, // debug mode, showing the coordinates and the entities' internal state
+  pub effects: bool, // particles and screen shake
+  pub assists: Assists,
+  
+  #[serde(with = "::serialization::flag_set")]
+  pub flags: HashSet<Flag>,
+  pub script_variables: BTreeMap<String, i64>, // sorted, so that equal states are saved as the same bytes
 }
 
-pub fn initial_state() -> State {
+// The player enters the level through its left door, and the level's spinies and walls are where the level
+// description puts them, even those which would have moved during the previous levels.
+pub fn level_state(level_number: LevelNumber) -> State {
   let t = 0.0;
-  let level_number = 1;
   let previous_level = level_number - 1;
   
   State {
     time: t,
-    message: Some(".............................................\n\
-                   .                                           .\n\
-                   .            I've Seen This Room            .\n\
-                   .               Twice Already               .\n\
-                   .                                           .\n\
-                   .                                           .\n\
-                   .                                           .\n\
-                   .                                           .\n\
-                   .    made in 72 hours by Samuel Gélineau    .\n\
-                   .             for Ludum Dare 37             .\n\
-                   .                                           .\n\
-                   .              theme: One Room              .\n\
-                   .                                           .\n\
-                   .                                           .\n\
-                   .           press any key to begin          .\n\
-                   .                                           .\n\
-                   ............................................."),
+    message: None,
+    message_page: 0,
+    selected_choice: 0,
     frozen: false,
     
     level_number: level_number,
     previous_level: previous_level,
     next_level: None,
     
-    player: Player {
-      up_pressed:    false,
-      left_pressed:  false,
-      down_pressed:  false,
-      right_pressed: false,
-      most_recent_dir: None,
-      buffered_dir:    None,
-      pos: moving_in(previous_level, level_number, t + PLAYER_MOVE_DURATION),
-    },
+    players: vec![entering_player(previous_level, level_number, t + PLAYER_MOVE_DURATION)],
     corpses: VecDeque::new(),
+    particles: Vec::new(),
+    shake_t0: None,
     
     spinies_moving_since: t,
-    spinies: adjust_spinies(Vec::new(), previous_level, level_number),
+    spinies_speed: SPINY_SPEED,
+    spiny_steps: 0,
+    spinies: adjust_spinies(Vec::new(), min_level() - 1, level_number),
+    spiny_changes: Vec::new(),
     
-    temporary_walls: adjust_walls(Vec::new(), previous_level, level_number),
+    temporary_walls: adjust_walls(Vec::new(), min_level() - 1, level_number),
+    
+    memory_overlay: false,
+    inspector_overlay: false,
+    effects: true,
+    assists: DEFAULT_ASSISTS,
+    
+    flags: HashSet::new(),
+    script_variables: BTreeMap::new(),
+  }
+}
+
+pub fn initial_state() -> State {
+  let mut state = level_state(min_level());
+  state.message = Some(TitleM);
+  
+  state
+}
+
+// The extra players walk in through the left door, like the first player did.
+pub fn set_player_count(state: &mut State, player_count: usize) {
+  state.players.truncate(player_count);
+  while state.players.len() < player_count {
+    let player = entering_player(state.level_number - 1, state.level_number, state.time + PLAYER_MOVE_DURATION);
+    state.players.push(player);
   }
 }
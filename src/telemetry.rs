@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fs::{ self, File };
+use std::io::{ self, Read, Write };
+use std::path::{ Path, PathBuf };
+
+use settings::*;
+use state::*;
+use types::*;
+use types::AnimatedPos::*;
+
+
+// Where the player goes, hesitates and dies, for the level designers' heatmaps. Nothing is sent anywhere: with
+// "--telemetry DIR", each session is added to one file per level in DIR when the game exits, see format_telemetry.
+pub fn telemetry_setting() -> Option<String> {
+  command_line_option("telemetry")
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct CellTelemetry {
+  pub visits: u32, // how many times the player stood in the cell, whether or not they stopped there
+  pub duration: Seconds,
+}
+
+#[derive(Clone,PartialEq,Debug)]
+pub struct LevelTelemetry {
+  pub cells: HashMap<Pos, CellTelemetry>,
+  pub deaths: Vec<FPos>,
+}
+
+pub struct TelemetryRecorder {
+  pub levels: HashMap<LevelNumber, LevelTelemetry>,
+  previous_cells: Vec<Option<(LevelNumber, Pos)>>, // one per player
+  previous_time: Seconds,
+}
+
+
+pub fn new_level_telemetry() -> LevelTelemetry {
+  LevelTelemetry {
+    cells: HashMap::new(),
+    deaths: Vec::new(),
+  }
+}
+
+pub fn new_telemetry_recorder(state: &State) -> TelemetryRecorder {
+  TelemetryRecorder {
+    levels: HashMap::new(),
+    previous_cells: Vec::new(),
+    previous_time: state.time,
+  }
+}
+
+// The cell which the player stands on or is leaving, or None while they go through a door.
+fn player_cell(pos: &AnimatedPos) -> Option<Pos> {
+  match *pos {
+    Idle(pos) | MovingSince(pos, _, _) => Some(pos),
+    _                                  => None,
+  }
+}
+
+fn level_telemetry(levels: &mut HashMap<LevelNumber, LevelTelemetry>, level_number: LevelNumber) -> &mut LevelTelemetry {
+  levels.entry(level_number).or_insert_with(new_level_telemetry)
+}
+
+fn cell_telemetry(level: &mut LevelTelemetry, pos: Pos) -> &mut CellTelemetry {
+  level.cells.entry(pos).or_insert(CellTelemetry { visits: 0, duration: 0.0 })
+}
+
+// Called after each update. Only the game time counts, so the time spent reading signs or paused doesn't.
+pub fn record_telemetry(recorder: &mut TelemetryRecorder, state: &State) {
+  // a new game starts over at t=0
+  if state.time < recorder.previous_time {
+    recorder.previous_time = state.time;
+  }
+  let dt = state.time - recorder.previous_time;
+  recorder.previous_cells.resize(state.players.len(), None);
+  
+  for &previous_cell in &recorder.previous_cells {
+    if let Some((level_number, pos)) = previous_cell {
+      cell_telemetry(level_telemetry(&mut recorder.levels, level_number), pos).duration += dt;
+    }
+  }
+  
+  let level = level_telemetry(&mut recorder.levels, state.level_number);
+  for corpse in &state.corpses {
+    if corpse.t0 > recorder.previous_time {
+      level.deaths.push(corpse.f_pos);
+    }
+  }
+  
+  for (player, previous_cell) in state.players.iter().zip(recorder.previous_cells.iter_mut()) {
+    let cell = player_cell(&player.pos).map(|pos| (state.level_number, pos));
+    if let Some((_, pos)) = cell {
+      if cell != *previous_cell {
+        cell_telemetry(level, pos).visits += 1;
+      }
+    }
+    *previous_cell = cell;
+  }
+  
+  recorder.previous_time = state.time;
+}
+
+
+fn merge_level_telemetry(level: &mut LevelTelemetry, other: &LevelTelemetry) {
+  for (&pos, other_cell) in &other.cells {
+    let cell = cell_telemetry(level, pos);
+    cell.visits += other_cell.visits;
+    cell.duration += other_cell.duration;
+  }
+  level.deaths.extend_from_slice(&other.deaths);
+}
+
+// A level's file looks like this, with the cells sorted by position:
+//
+//   # comment
+//   cell 3 2 5 1.25    # x, y, visits, seconds
+//   death 3.5 2.0      # where the player was when they died
+pub fn format_telemetry(level: &LevelTelemetry) -> String {
+  let mut cells: Vec<(&Pos, &CellTelemetry)> = level.cells.iter().collect();
+  cells.sort_by_key(|&(pos, _)| [pos[1], pos[0]]);
+  
+  let mut text = String::new();
+  for (pos, cell) in cells {
+    text.push_str(&format!("cell {} {} {} {:?}\n", pos[0], pos[1], cell.visits, cell.duration));
+  }
+  for f_pos in &level.deaths {
+    text.push_str(&format!("death {:?} {:?}\n", f_pos[0], f_pos[1]));
+  }
+  
+  text
+}
+
+fn parse_word<A: ::std::str::FromStr>(word: Option<&str>, what: &str) -> Result<A, String> {
+  word.and_then(|word| word.parse().ok()).ok_or(format!("syntax error in telemetry: expected {}", what))
+}
+
+pub fn parse_telemetry(text: &str) -> Result<LevelTelemetry, String> {
+  let mut level = new_level_telemetry();
+  
+  for line in text.lines() {
+    let line = line.split('#').next().unwrap();
+    let mut words = line.split_whitespace();
+    
+    match words.next() {
+      None          => {},
+      Some("cell")  => {
+        let pos = [parse_word(words.next(), "an x coordinate")?, parse_word(words.next(), "a y coordinate")?];
+        let visits: u32 = parse_word(words.next(), "a number of visits")?;
+        let duration: Seconds = parse_word(words.next(), "a duration")?;
+        
+        // the same cell may appear twice if the file was edited by hand
+        let cell = cell_telemetry(&mut level, pos);
+        cell.visits += visits;
+        cell.duration += duration;
+      },
+      Some("death") => {
+        let f_pos = [parse_word(words.next(), "an x coordinate")?, parse_word(words.next(), "a y coordinate")?];
+        level.deaths.push(f_pos);
+      },
+      Some(word)    => return Err(format!("syntax error in telemetry: unknown entry {:?}", word)),
+    }
+  }
+  
+  Ok(level)
+}
+
+fn telemetry_path(directory: &str, level_number: LevelNumber) -> PathBuf {
+  Path::new(directory).join(format!("level-{}.txt", level_number))
+}
+
+// Empty if no session has been recorded in that level yet.
+pub fn load_telemetry(directory: &str, level_number: LevelNumber) -> Result<LevelTelemetry, String> {
+  let path = telemetry_path(directory, level_number);
+  let mut text = String::new();
+  match File::open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+    Ok(_)                                                 => {},
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(new_level_telemetry()),
+    Err(err)                                              => return Err(format!("could not read {}: {}", path.display(), err)),
+  }
+  
+  parse_telemetry(&text).map_err(|err| format!("{} in {}", err, path.display()))
+}
+
+// Adds the session to the files of the previous sessions.
+pub fn save_telemetry(recorder: &TelemetryRecorder, directory: &str) -> Result<(), String> {
+  fs::create_dir_all(directory).map_err(|err| format!("could not create {}: {}", directory, err))?;
+  
+  for (&level_number, session) in &recorder.levels {
+    let mut level = load_telemetry(directory, level_number)?;
+    merge_level_telemetry(&mut level, session);
+    
+    let path = telemetry_path(directory, level_number);
+    File::create(&path).and_then(|mut file| file.write_all(format_telemetry(&level).as_bytes()))
+                       .map_err(|err| format!("could not write {}: {}", path.display(), err))?;
+  }
+  
+  Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use audio::*;
+  use game::*;
+  use script::*;
+  use types::RawInputEvent::*;
+  
+  
+  fn play(state: &mut State, recorder: &mut TelemetryRecorder, events: &[RawInputEvent]) {
+    let scripts = load_scripts();
+    let mut audio = NullAudio;
+    for &event in events {
+      update(state, &scripts, &mut audio, event);
+      record_telemetry(recorder, state);
+    }
+  }
+  
+  fn wait(seconds: Seconds) -> Vec<RawInputEvent> {
+    vec![TimePasses(1.0 / 60.0); (seconds * 60.0) as usize]
+  }
+  
+  
+  #[test]
+  fn cells_and_deaths_are_recorded() {
+    let mut state = level_state(4);
+    let mut recorder = new_telemetry_recorder(&state);
+    play(&mut state, &mut recorder, &wait(1.0));
+    for _ in 0..4 {
+      play(&mut state, &mut recorder, &[PressRight(0), ReleaseRight(0)]);
+      play(&mut state, &mut recorder, &wait(0.3));
+    }
+    play(&mut state, &mut recorder, &wait(0.5));
+    
+    let level = &recorder.levels[&4];
+    assert_eq!(level.cells[&[0, 1]].visits, 1);
+    assert!(level.cells[&[0, 1]].duration > 0.4);
+    assert_eq!(level.deaths.len(), 1);
+    assert_eq!(level.deaths[0][1], 1.0);
+  }
+  
+  #[test]
+  fn sessions_are_added_up() {
+    let text = "cell 0 1 1 0.5\ncell 3 1 2 0.25\ndeath 2.5 1.0\n";
+    let mut level = parse_telemetry(text).unwrap();
+    assert_eq!(format_telemetry(&level), text);
+    
+    let other = level.clone();
+    merge_level_telemetry(&mut level, &other);
+    assert_eq!(format_telemetry(&level), "cell 0 1 2 1.0\ncell 3 1 4 0.5\ndeath 2.5 1.0\ndeath 2.5 1.0\n");
+    
+    assert!(parse_telemetry("cell 0 1 many 0.5").is_err());
+    assert!(parse_telemetry("visit 0 1").is_err());
+  }
+}
@@ -0,0 +1,336 @@
+extern crate termion;
+
+use std::io::{ self, Write };
+use std::thread;
+use std::time::{ Duration, Instant };
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use audio::*;
+use game::*;
+use i18n::*;
+use inspector::*;
+use layout::*;
+use levels::*;
+use levels::Cell::*;
+use netplay::*;
+use player::*;
+use script::*;
+use settings::*;
+use spiny::*;
+use state::*;
+use telemetry::*;
+use time_control::*;
+use types::*;
+use types::AnimatedPos::*;
+use types::RawInputEvent::*;
+
+
+// "--terminal" plays the game in the terminal instead of a window, e.g. over SSH.
+pub fn terminal_setting() -> bool {
+  command_line_flag("terminal")
+}
+
+const FRAME_DURATION: Seconds = 1.0 / 60.0;
+
+// Terminals only tell us when a key is pressed, and then repeat it while it is held, so a key counts as held until
+// it hasn't been repeated for this long. It is shorter than a step, so that a tap only moves the player by one cell.
+const KEY_HOLD_DURATION: Seconds = 0.1;
+
+// The direction keys which are currently held, and until when.
+struct HeldKeys {
+  up:    Option<Seconds>,
+  left:  Option<Seconds>,
+  down:  Option<Seconds>,
+  right: Option<Seconds>,
+}
+
+enum TerminalInput {
+  DirectionI(PlayerIndex, Dir),
+  EventI(RawInputEvent),
+  SlowMotionI, FreezeI, StepI,
+  QuitI,
+}
+
+
+fn arrow(dir: Dir) -> char {
+  match dir {
+    UP    => '^',
+    LEFT  => '<',
+    DOWN  => 'v',
+    RIGHT => '>',
+    _     => '?',
+  }
+}
+
+fn rounded_pos(f_pos: FPos) -> Pos {
+  [f_pos[0].round() as i8, f_pos[1].round() as i8]
+}
+
+// The room using the same glyphs as the level descriptions, with "PP" for the player, "QQ" for the second player
+// in co-op mode, and "++" for the corpses. Moving entities are drawn in the cell they overlap the most.
+pub fn ascii_room(state: &State) -> Vec<String> {
+  let player_positions: Vec<Option<Pos>> = state.players.iter().map(|player| match player.pos {
+                                             Idle(_) | MovingSince(_, _, _) =>
+                                               Some(rounded_pos(compute_player_f_pos(&player.pos, state.time))),
+                                             _ => None, // outside the room
+                                           }).collect();
+  
+  let mut lines = vec![" . . . . . . . . . .".to_string()];
+  for j in 0..LEVEL_HEIGHT {
+    let mut line = ".".to_string();
+    for i in 0..LEVEL_WIDTH {
+      let pos = [i, j];
+      // overlapping spinies are disabled until they separate, so the enabled one is the one which is moving
+      let spinies_here: Vec<&MovingSpiny> = state.spinies.iter().filter(|spiny| {
+        rounded_pos(compute_spiny_f_pos(spiny, state.spinies_speed, state.spinies_moving_since, state.time)) == pos
+      }).collect();
+      let spiny = spinies_here.iter().find(|spiny| spiny.enabled).or(spinies_here.first());
+      let corpse = state.corpses.iter().any(|corpse| rounded_pos(corpse.f_pos) == pos);
+      
+      let player_index = player_positions.iter().position(|&player_pos| player_pos == Some(pos));
+      
+      let glyph = if let Some(player_index) = player_index {
+                    if player_index == 0 { "PP".to_string() } else { "QQ".to_string() }
+                  } else if let Some(spiny) = spiny {
+                    format!("{}{}", arrow(spiny.dir), arrow(spiny.dir))
+                  } else if corpse {
+                    "++".to_string()
+                  } else {
+                    match current_cell_at(state.level_number, &state.temporary_walls, pos) {
+                      FloorC | SpinyC(_) => "  ".to_string(), // the spinies have moved on
+                      WallC              => "##".to_string(), // possibly a temporary wall, see below
+                      _                  => ascii_code_at(state.level_number, pos),
+                    }
+                  };
+      line.push_str(&glyph);
+    }
+    line.push('.');
+    lines.push(line);
+  }
+  
+  lines
+}
+
+// e.g. "## 3,2: 4-6", the levels in which the temporary walls exist
+fn temporary_wall_lines(state: &State) -> Vec<String> {
+  state.temporary_walls.iter().map(|temporary_wall| {
+    format!("## {},{}: {}-{}",
+            temporary_wall.pos[0], temporary_wall.pos[1],
+            temporary_wall.lifetime.level_min, temporary_wall.lifetime.level_max)
+  }).collect()
+}
+
+fn screen_lines(state: &State, time_control: &TimeControl, catalog: &Catalog, columns: usize) -> Vec<String> {
+  let mut lines = ascii_room(state);
+  match time_control_label(time_control) {
+    Some(label) => lines.push(format!(" Level {} ({})", state.level_number, label)),
+    None        => lines.push(format!(" Level {}", state.level_number)),
+  }
+  lines.extend(temporary_wall_lines(state).into_iter().map(|line| format!(" {}", line)));
+  if state.inspector_overlay {
+    lines.push(String::new());
+    lines.extend(inspector_lines(state).into_iter().map(|line| format!(" {}", line)));
+  }
+  
+  if let Some(message) = state.message {
+    lines.push(String::new());
+    lines.extend(layout_message_box(&message_box(catalog, state, message), columns));
+  }
+  
+  lines
+}
+
+// Same keys as in the window, except that Escape and "q" quit.
+fn terminal_input(key: Key, wasd_player: PlayerIndex) -> Option<TerminalInput> {
+  use self::TerminalInput::*;
+  
+  match key {
+    Key::Up    | Key::Char('k')                  => Some(DirectionI(0, UP)),
+    Key::Left  | Key::Char('h')                  => Some(DirectionI(0, LEFT)),
+    Key::Down  | Key::Char('j')                  => Some(DirectionI(0, DOWN)),
+    Key::Right | Key::Char('l')                  => Some(DirectionI(0, RIGHT)),
+    Key::Char('w')                               => Some(DirectionI(wasd_player, UP)),
+    Key::Char('a')                               => Some(DirectionI(wasd_player, LEFT)),
+    Key::Char('s')                               => Some(DirectionI(wasd_player, DOWN)),
+    Key::Char('d')                               => Some(DirectionI(wasd_player, RIGHT)),
+    
+    Key::Char('p') | Key::Char(' ')              => Some(EventI(PressPause)),
+    Key::Char('o')                               => Some(EventI(PressAssists)),
+    Key::F(3)                                    => Some(EventI(PressInspector)),
+    Key::F(5)                                    => Some(SlowMotionI),
+    Key::F(6)                                    => Some(FreezeI),
+    Key::F(7)                                    => Some(StepI),
+    Key::Esc | Key::Char('q') | Key::Ctrl('c')   => Some(QuitI),
+    Key::Char(_)                                 => Some(EventI(PressAnyKey)),
+    _                                            => None,
+  }
+}
+
+fn held_key(held_keys: &mut HeldKeys, dir: Dir) -> &mut Option<Seconds> {
+  match dir {
+    UP    => &mut held_keys.up,
+    LEFT  => &mut held_keys.left,
+    DOWN  => &mut held_keys.down,
+    _     => &mut held_keys.right,
+  }
+}
+
+fn press_event(player_index: PlayerIndex, dir: Dir) -> RawInputEvent {
+  match dir {
+    UP    => PressUp(player_index),
+    LEFT  => PressLeft(player_index),
+    DOWN  => PressDown(player_index),
+    _     => PressRight(player_index),
+  }
+}
+
+fn release_event(player_index: PlayerIndex, dir: Dir) -> RawInputEvent {
+  match dir {
+    UP    => ReleaseUp(player_index),
+    LEFT  => ReleaseLeft(player_index),
+    DOWN  => ReleaseDown(player_index),
+    _     => ReleaseRight(player_index),
+  }
+}
+
+// Releasing the arrow key which walked into a sign closes it, so while a message is displayed, we wait for the next
+// key press before releasing the keys. Otherwise the message would disappear before the player could read it.
+fn release_keys(held_keys: &mut Vec<HeldKeys>, state: &State, t: Seconds, force: bool) -> Vec<RawInputEvent> {
+  let mut events = Vec::new();
+  if state.message.is_some() && !force {
+    return events;
+  }
+  
+  for (player_index, player_keys) in held_keys.iter_mut().enumerate() {
+    for &dir in &[UP, LEFT, DOWN, RIGHT] {
+      let held_until = held_key(player_keys, dir);
+      if held_until.map_or(false, |held_until| force || t >= held_until) {
+        *held_until = None;
+        events.push(release_event(player_index, dir));
+      }
+    }
+  }
+  
+  events
+}
+
+// Over the network, the events wait for the other player, see netplay_update.
+fn terminal_update(
+  netplay: &mut Option<Netplay>,
+  state: &mut State,
+  scripts: &Scripts,
+  audio: &mut dyn Audio,
+  raw_input_event: RawInputEvent
+) -> Result<(), String> {
+  match *netplay {
+    Some(ref mut netplay) => netplay_update(netplay, state, scripts, audio, raw_input_event).map(|_| ()),
+    None                  => {
+      update(state, scripts, audio, raw_input_event);
+      Ok(())
+    },
+  }
+}
+
+pub fn run_terminal(scripts: &Scripts) -> Result<(), String> {
+  use self::TerminalInput::*;
+  
+  let error = |err: io::Error| format!("could not use the terminal: {}", err);
+  
+  // before the alternate screen, so the host sees that they are waiting
+  let mut netplay = match netplay_setting() {
+    Some(role) => Some(start_netplay(&role)?),
+    None       => None,
+  };
+  
+  let catalog = load_catalog(&language_setting());
+  let stdout = io::stdout().into_raw_mode().map_err(&error)?;
+  let mut screen = AlternateScreen::from(stdout);
+  let mut keys = termion::async_stdin().keys();
+  write!(screen, "{}", termion::cursor::Hide).map_err(&error)?;
+  
+  let mut state = initial_state();
+  if coop_setting() || netplay.is_some() {
+    set_player_count(&mut state, 2);
+  }
+  let mut audio = NullAudio;
+  let mut held_keys: Vec<HeldKeys> = state.players.iter().map(|_| HeldKeys { up: None, left: None, down: None, right: None }).collect();
+  let mut time_control = new_time_control();
+  let mut previous_instant = Instant::now();
+  let telemetry_path = telemetry_setting();
+  let mut telemetry = new_telemetry_recorder(&state);
+  
+  'game: loop {
+    let mut events = Vec::new();
+    while let Some(key) = keys.next() {
+      let wasd_player = state.players.len() - 1;
+      match key.map(|key| terminal_input(key, wasd_player)).map_err(&error)? {
+        Some(DirectionI(player_index, dir)) => {
+          // a new key press, as opposed to the repetition of a held key, e.g. to highlight the choices of a sign
+          let repeated = held_key(&mut held_keys[player_index], dir).map_or(false, |held_until| state.time < held_until);
+          if state.message.is_some() && !repeated {
+            events.extend(release_keys(&mut held_keys, &state, state.time, true));
+          }
+          
+          let held_until = held_key(&mut held_keys[player_index], dir);
+          if held_until.is_none() {
+            events.push(press_event(player_index, dir));
+          }
+          *held_until = Some(state.time + KEY_HOLD_DURATION);
+        },
+        Some(EventI(event))                 => {
+          events.extend(release_keys(&mut held_keys, &state, state.time, true));
+          events.push(event);
+        },
+        // the time controls would make the other player wait for us over the network
+        Some(SlowMotionI) | Some(FreezeI) | Some(StepI) if netplay.is_some() => {},
+        Some(SlowMotionI)                   => cycle_slow_motion(&mut time_control),
+        Some(FreezeI)                       => toggle_freeze(&mut time_control),
+        Some(StepI)                         => events.extend(step_events(&mut time_control, &state)),
+        Some(QuitI)                         => break 'game,
+        None                                => {},
+      }
+    }
+    
+    let now = Instant::now();
+    let elapsed = now.duration_since(previous_instant);
+    previous_instant = now;
+    if let Some(dt) = scaled_dt(&time_control, elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9) {
+      events.push(TimePasses(dt));
+    }
+    
+    for event in events {
+      terminal_update(&mut netplay, &mut state, scripts, &mut audio, event)?;
+    }
+    for event in release_keys(&mut held_keys, &state, state.time, false) {
+      terminal_update(&mut netplay, &mut state, scripts, &mut audio, event)?;
+    }
+    if telemetry_path.is_some() {
+      record_telemetry(&mut telemetry, &state);
+    }
+    
+    // some terminals, e.g. over a serial line, don't know their size
+    let columns = match termion::terminal_size() {
+                    Ok((columns, _)) if columns > 0 => columns as usize,
+                    _                               => 80,
+                  };
+    write!(screen, "{}", termion::cursor::Goto(1, 1)).map_err(&error)?;
+    for line in screen_lines(&state, &time_control, &catalog, columns) {
+      write!(screen, "{}{}\r\n", line, termion::clear::UntilNewline).map_err(&error)?;
+    }
+    write!(screen, "{}", termion::clear::AfterCursor).map_err(&error)?;
+    screen.flush().map_err(&error)?;
+    
+    thread::sleep(Duration::from_millis((FRAME_DURATION * 1000.0) as u64));
+  }
+  
+  write!(screen, "{}", termion::cursor::Show).map_err(&error)?;
+  screen.flush().map_err(&error)?;
+  
+  match telemetry_path {
+    Some(path) => save_telemetry(&telemetry, &path),
+    None       => Ok(()),
+  }
+}
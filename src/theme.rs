@@ -0,0 +1,108 @@
+extern crate graphics;
+
+use std::path::{ Path, PathBuf };
+use graphics::types::Color;
+
+use assets::*;
+use settings::*;
+
+
+// The images which aren't in the theme's directory are loaded from here. Like all the asset paths, it is relative to
+// the asset root, see asset_roots.
+pub const DEFAULT_IMAGE_DIRECTORY: &'static str = "images";
+
+pub struct Theme {
+  pub directory: Option<PathBuf>,
+  pub clear_color:         Color,
+  pub overlay_color:       Color, // drawn between the room and the messages, to make the text more readable
+  pub lifetime_color:      Color, // multiplies the small font's colors
+  pub lifetime_background: Option<Color>,
+  pub previous_level_tint: Color, // for the hint mode
+  pub next_level_tint:     Color,
+}
+
+fn default_theme() -> Theme {
+  Theme {
+    directory: None,
+    clear_color:         [1.0, 1.0, 1.0, 1.0],
+    overlay_color:       [1.0, 1.0, 1.0, 0.8],
+    lifetime_color:      [1.0, 1.0, 1.0, 1.0],
+    lifetime_background: None,
+    previous_level_tint: [0.3, 0.5, 1.0, 0.4],
+    next_level_tint:     [1.0, 0.5, 0.2, 0.4],
+  }
+}
+
+
+fn parse_color(words: &mut ::std::str::SplitWhitespace) -> Result<Color, String> {
+  let mut color = [0.0; 4];
+  for component in color.iter_mut() {
+    *component = words.next()
+                      .and_then(|word| word.parse().ok())
+                      .ok_or("syntax error in theme description: expected four numbers")?;
+  }
+  
+  Ok(color)
+}
+
+// A theme description looks like this, and the colors which aren't mentioned keep their default value:
+//
+//   # comment
+//   clear_color         1.0 1.0 1.0 1.0   # red, green, blue and alpha
+//   lifetime_background 1.0 1.0 1.0 1.0   # draw the lifetime labels over a rectangle of that color
+fn parse_theme_description(text: &str, theme: &mut Theme) -> Result<(), String> {
+  for line in text.lines() {
+    let line = line.split('#').next().unwrap();
+    let mut words = line.split_whitespace();
+    
+    match words.next() {
+      None                        => {},
+      Some("clear_color")         => theme.clear_color         = parse_color(&mut words)?,
+      Some("overlay_color")       => theme.overlay_color       = parse_color(&mut words)?,
+      Some("lifetime_color")      => theme.lifetime_color      = parse_color(&mut words)?,
+      Some("lifetime_background") => theme.lifetime_background = Some(parse_color(&mut words)?),
+      Some("previous_level_tint") => theme.previous_level_tint = parse_color(&mut words)?,
+      Some("next_level_tint")     => theme.next_level_tint     = parse_color(&mut words)?,
+      Some(word)                  => return Err(format!("syntax error in theme description: unknown setting {:?}", word)),
+    }
+  }
+  
+  Ok(())
+}
+
+// Each theme is a directory "themes/NAME" containing a "theme.txt" description and the images which differ from the
+// default ones. If the theme can't be loaded, we use the default theme instead.
+pub fn load_theme(name: Option<String>) -> Theme {
+  if let Some(name) = name {
+    let directory = Path::new("themes").join(&name);
+    let path = directory.join("theme.txt");
+    let mut theme = default_theme();
+    match read_text_asset(&path).and_then(|text| parse_theme_description(&text, &mut theme)) {
+      Ok(())   => {
+        theme.directory = Some(directory);
+        return theme;
+      },
+      Err(err) => {
+        eprintln!("could not load theme {}: {}", name, err);
+      },
+    }
+  }
+  
+  default_theme()
+}
+
+// The theme can be chosen with "--theme high-contrast", otherwise we use the default images and colors.
+pub fn theme_setting() -> Option<String> {
+  command_line_option("theme")
+}
+
+pub fn image_path(theme: &Theme, file_name: &str) -> PathBuf {
+  if let Some(ref directory) = theme.directory {
+    let path = directory.join(file_name);
+    if asset_exists(&path) {
+      return path;
+    }
+  }
+  
+  Path::new(DEFAULT_IMAGE_DIRECTORY).join(file_name)
+}
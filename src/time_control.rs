@@ -0,0 +1,118 @@
+use state::*;
+use types::*;
+
+
+// Debug controls which slow down or stop the passage of time, in order to study the collisions. Unlike the pause
+// message, they don't hide the room, and they are not part of the State: they only change the TimePasses events
+// which reach the game, so the recordings still reproduce the session exactly.
+pub struct TimeControl {
+  pub speed_index: usize, // in SLOW_MOTION_SPEEDS
+  pub frozen: bool,
+}
+
+const SLOW_MOTION_SPEEDS: &'static [f64] = &[1.0, 0.5, 0.25, 0.125];
+
+
+pub fn new_time_control() -> TimeControl {
+  TimeControl {
+    speed_index: 0,
+    frozen: false,
+  }
+}
+
+// F5: each press halves the speed, until it goes back to normal.
+pub fn cycle_slow_motion(time_control: &mut TimeControl) {
+  time_control.speed_index = (time_control.speed_index + 1) % SLOW_MOTION_SPEEDS.len();
+}
+
+// F6
+pub fn toggle_freeze(time_control: &mut TimeControl) {
+  time_control.frozen = !time_control.frozen;
+}
+
+// None if the time is frozen.
+pub fn scaled_dt(time_control: &TimeControl, dt: Seconds) -> Option<Seconds> {
+  if time_control.frozen {
+    None
+  } else {
+    Some(dt * SLOW_MOTION_SPEEDS[time_control.speed_index])
+  }
+}
+
+// F7: the time until the spinies are aligned with the grid again, that is, until spinies_moving_since changes.
+// It freezes the time, so the spinies can be studied one step at a time. The step is split at the middle of the
+// move, so that, like the frames of the game, it reaches each moment at which the spinies collide on its own.
+pub fn step_events(time_control: &mut TimeControl, state: &State) -> Vec<RawInputEvent> {
+  time_control.frozen = true;
+  
+  let move_duration = 1.0 / state.spinies_speed;
+  let mut time = state.time;
+  let mut events = Vec::new();
+  for &target in &[state.spinies_moving_since + move_duration / 2.0, state.spinies_moving_since + move_duration] {
+    if time < target {
+      let mut dt = target - time;
+      
+      // the rounding of time + dt must not fall short of the target
+      while time + dt < target {
+        dt = f64::from_bits(dt.to_bits() + 1);
+      }
+      
+      events.push(RawInputEvent::TimePasses(dt));
+      time += dt;
+    }
+  }
+  
+  events
+}
+
+// e.g. "x0.25", or "frozen"
+pub fn time_control_label(time_control: &TimeControl) -> Option<String> {
+  if time_control.frozen {
+    Some("frozen".to_string())
+  } else if time_control.speed_index > 0 {
+    Some(format!("x{}", SLOW_MOTION_SPEEDS[time_control.speed_index]))
+  } else {
+    None
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use audio::*;
+  use game::*;
+  use script::*;
+  
+  
+  const STEP_COUNT: u32 = 20;
+  
+  fn spiny_moves(state: &State) -> Vec<(Pos, Dir)> {
+    state.spinies.iter().map(|spiny| (spiny.pos, spiny.dir)).collect()
+  }
+  
+  // Stepping shows the same bounces as the game, only one move at a time.
+  #[test]
+  fn steps_bounce_like_the_game() {
+    let scripts = load_scripts();
+    let mut audio = NullAudio;
+    let mut time_control = new_time_control();
+    
+    let mut stepped = level_state(4);
+    let initial_moves = spiny_moves(&stepped);
+    for _ in 0..STEP_COUNT {
+      for event in step_events(&mut time_control, &stepped) {
+        update(&mut stepped, &scripts, &mut audio, event);
+      }
+      assert_eq!(stepped.time, stepped.spinies_moving_since);
+    }
+    assert_eq!(stepped.spiny_steps, STEP_COUNT);
+    assert!(spiny_moves(&stepped).iter().zip(&initial_moves).any(|(&(_, dir), &(_, initial_dir))| dir != initial_dir));
+    
+    let mut played = level_state(4);
+    while played.spiny_steps < STEP_COUNT {
+      update(&mut played, &scripts, &mut audio, RawInputEvent::TimePasses(1.0 / 60.0));
+    }
+    assert_eq!(spiny_moves(&stepped), spiny_moves(&played));
+  }
+}
@@ -16,12 +16,9 @@ pub const PLAYER_SPEED: f64 = 4.0;
 pub const SPINY_SPEED:  f64 = 8.0;
 
 // time to cross cell
-pub const PLAYER_MOVE_DURATION:     Seconds = 1.0 / PLAYER_SPEED;
-pub const SPINY_MOVE_DURATION:      Seconds = 1.0 / SPINY_SPEED;
-pub const SPINY_HALF_MOVE_DURATION: Seconds = SPINY_MOVE_DURATION / 2.0;
+pub const PLAYER_MOVE_DURATION: Seconds = 1.0 / PLAYER_SPEED;
 
-pub const CORPSE_FADE_OUT_DURATION: Seconds = 1.0;
-pub const CORPSE_SPEED: f64 = 1.0 / CORPSE_FADE_OUT_DURATION; // fraction of alpha to remove per second
+pub const CORPSE_FADE_OUT_DURATION: Seconds = 1.0; // should match the player's "death" animation
 
 pub const TRANSITION_EXTRA_DURATION:      Seconds = 0.6;
 pub const TRANSITION_FLASH_DURATION:      Seconds = 0.2;
@@ -53,18 +50,21 @@ pub fn compute_f_pos(pos: Pos, dir: Dir, speed: f64, t0: Seconds, t: Seconds) ->
   linear_motion(pos, compute_f_speed(dir, speed), t0, t)
 }
 
-pub fn compute_f_rect(f_pos: FPos) -> FRect {
+// smaller collision rectangles to account for the round sprites, the assists can make them even smaller
+pub const DEFAULT_HITBOX_MARGIN: f64 = 0.15;
+
+pub fn compute_f_rect(f_pos: FPos, margin: f64) -> FRect {
   // all sprites are 1 cell by 1 cell
   let f_rect = [f_pos[0], f_pos[1], 1.0, 1.0];
   
-  // smaller collision rectangles to account for the round sprites
-  margin_rectangle(f_rect, 0.15)
+  margin_rectangle(f_rect, margin)
 }
 
 
 pub type LevelNumber = u8;
 
 // lives from level_min to level_max, inclusive
+#[derive(Clone,Serialize,Deserialize)]
 pub struct Lifetime {
   pub level_min: LevelNumber,
   pub level_max: LevelNumber,
@@ -75,29 +75,57 @@ pub fn still_alive(lifetime: &Lifetime, level_number: LevelNumber) -> bool {
 }
 
 
-pub type Message = &'static str;
+// The text depends on the language, so we only keep track of which message to display.
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum Message {
+  TitleM,
+  PausedM,
+  TheEndM,
+  AssistsM,
+  SignM(LevelNumber, u8), // "S0" is sign 0
+  ScriptM(&'static str), // a catalog key chosen by a script, or the text itself if there is no such key
+}
+
 pub type Radians = f64;
 
+// Set by the player's choices in the sign dialogues, e.g. "confused-by-numbers".
+pub type Flag = &'static str;
+
 
+// 0 for the first player, 1 for the second player in co-op mode
+pub type PlayerIndex = usize;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
 pub enum RawInputEvent {
   TimePasses(Seconds),
-  PressUp,    ReleaseUp,
-  PressLeft,  ReleaseLeft,
-  PressDown,  ReleaseDown,
-  PressRight, ReleaseRight,
+  PressUp(PlayerIndex),    ReleaseUp(PlayerIndex),
+  PressLeft(PlayerIndex),  ReleaseLeft(PlayerIndex),
+  PressDown(PlayerIndex),  ReleaseDown(PlayerIndex),
+  PressRight(PlayerIndex), ReleaseRight(PlayerIndex),
   PressPause,
+  PressMemory,
+  PressInspector,
+  PressAssists,
   PressAnyKey,
 }
 
+#[derive(Clone)]
 pub enum Action {
-  Move(Pos, Dir),
+  Move(PlayerIndex, Pos, Dir),
+  Slide(PlayerIndex, Pos, Dir), // like Move, but not initiated by the player, e.g. on ice or on a conveyor
+  Bump,
   ReadSign(Message),
-  Die(FPos),
-  PreviousLevel, NextLevel, TransitionLevel(LevelNumber, LevelNumber),
+  NextPage,
+  HighlightChoice(u8),
+  Choose(Flag),
+  Die(PlayerIndex, FPos),
+  PreviousLevel(PlayerIndex), NextLevel(PlayerIndex), TransitionLevel(LevelNumber, LevelNumber),
   Pause, Unpause,
+  OpenAssists, ChangeAssist(u8), // the last choice closes the menu
 }
 
 
+#[derive(Clone,Serialize,Deserialize)]
 pub enum AnimatedPos {
   Idle(Pos),
   MovingSince(Pos, Dir, Seconds),
@@ -115,6 +143,7 @@ pub fn moving_in(level_src: LevelNumber, level_dst: LevelNumber, t: Seconds) ->
 
 
 
+#[derive(Clone,Serialize,Deserialize)]
 pub struct MovingSpiny {
   pub pos: Pos,
   pub dir: Dir,
@@ -122,6 +151,15 @@ pub struct MovingSpiny {
   pub enabled: bool,
 }
 
+// A change which a script made to the spinies. It waits until the spinies are aligned with the grid, so a spiny
+// never appears or disappears in the middle of a move, and the position is the one at that moment.
+#[derive(Clone,Serialize,Deserialize)]
+pub enum SpinyChange {
+  AddSpinyC(MovingSpiny),
+  RemoveSpinyC(Pos),
+}
+
+#[derive(Clone,Serialize,Deserialize)]
 pub struct TemporaryWall {
   pub pos: Pos,
   pub lifetime: Lifetime,